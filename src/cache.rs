@@ -0,0 +1,109 @@
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Responses from start.gg are stored in the cache directory as
+// `<QUERY>/<HASH>.json`, where the hash is computed from the query document and
+// variables. The first line of each file holds the document's hash and the
+// variables themselves, so that hash collisions can be detected; the rest is
+// the raw response. Changing the fields a query selects changes its document,
+// so responses cached before the change are not used.
+
+static CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Enable the response cache, storing responses in the given directory.
+pub fn enable_cache(dir: PathBuf) {
+    let _ = CACHE_DIR.set(dir);
+}
+
+// 64-bit FNV-1a, used instead of `DefaultHasher` since cache file names must
+// not change between builds
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub struct CacheEntry {
+    path: PathBuf,
+    key: String,
+}
+
+impl CacheEntry {
+    /// Locate the cache entry for a query, if the cache is enabled.
+    pub fn new<Vars: Serialize>(query: &str, document: &str, vars: &Vars) -> Option<CacheEntry> {
+        let key = format!(
+            "{:016x} {}",
+            hash(document.as_bytes()),
+            serde_json::to_string(vars).ok()?
+        );
+
+        let mut path = CACHE_DIR.get()?.clone();
+        path.push(query);
+        path.push(format!("{:016x}.json", hash(key.as_bytes())));
+
+        Some(CacheEntry { path, key })
+    }
+
+    pub fn read(&self) -> Option<String> {
+        let contents = fs::read_to_string(&self.path).ok()?;
+        let (key, response) = contents.split_once('\n')?;
+        (key == self.key).then(|| response.to_owned())
+    }
+
+    pub fn write(&self, response: &str) -> io::Result<()> {
+        fs::create_dir_all(self.path.parent().unwrap())?;
+        fs::write(&self.path, format!("{}\n{}", self.key, response))
+    }
+}
+
+// Cache management
+
+pub struct CacheStats {
+    pub query: String,
+    pub entries: u64,
+    pub size: u64,
+}
+
+/// Count the entries stored for each query in the cache directory.
+pub fn cache_stats(dir: &Path) -> io::Result<Vec<CacheStats>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut stats = fs::read_dir(dir)?
+        .map(|query_dir| {
+            let query_dir = query_dir?;
+            let (entries, size) = fs::read_dir(query_dir.path())?.try_fold(
+                (0, 0),
+                |(entries, size), file| -> io::Result<_> {
+                    Ok((entries + 1, size + file?.metadata()?.len()))
+                },
+            )?;
+            Ok(CacheStats {
+                query: query_dir.file_name().to_string_lossy().into_owned(),
+                entries,
+                size,
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    stats.sort_by(|a, b| a.query.cmp(&b.query));
+    Ok(stats)
+}
+
+/// Delete cached responses, either for a single query or for all of them.
+pub fn purge_cache(dir: &Path, query: Option<&str>) -> io::Result<()> {
+    let path = match query {
+        Some(query) => dir.join(query),
+        None => dir.to_owned(),
+    };
+
+    if path.exists() {
+        fs::remove_dir_all(path)
+    } else {
+        Ok(())
+    }
+}
@@ -2,19 +2,84 @@ use crate::queries::*;
 use sqlite::*;
 use std::path::{Path, PathBuf};
 
+/// Which events a dataset tracks, based on whether they are online.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnlinePolicy {
+    Offline,
+    Online,
+    Both,
+}
+
+impl OnlinePolicy {
+    // Stored as whether events must be online, or NULL if both are tracked
+    fn to_sql(self) -> Option<i64> {
+        match self {
+            OnlinePolicy::Offline => Some(0),
+            OnlinePolicy::Online => Some(1),
+            OnlinePolicy::Both => None,
+        }
+    }
+
+    fn from_sql(value: Option<i64>) -> Self {
+        match value {
+            Some(0) => OnlinePolicy::Offline,
+            Some(_) => OnlinePolicy::Online,
+            None => OnlinePolicy::Both,
+        }
+    }
+
+    pub fn allows(self, online: bool) -> bool {
+        match self {
+            OnlinePolicy::Offline => !online,
+            OnlinePolicy::Online => online,
+            OnlinePolicy::Both => true,
+        }
+    }
+}
+
+/// An area that a dataset tracks tournaments in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Location {
+    /// A country, or a state/province within it
+    Region {
+        country: String,
+        state: Option<String>,
+    },
+    /// Everywhere within a distance (e.g. "150mi") of a point
+    Radius {
+        latitude: f64,
+        longitude: f64,
+        distance: String,
+    },
+}
+
+/// Rules deciding which events at a tournament a dataset includes.
+#[derive(Debug, Clone, Default)]
+pub struct EventRules {
+    /// Events whose slug or name contains one of these keywords are excluded
+    pub blocklist: Vec<String>,
+    pub min_entrants: u64,
+    pub singles_only: bool,
+}
+
 pub struct DatasetMetadata {
     pub start: Timestamp,
     pub end: Option<Timestamp>,
     pub last_sync: Timestamp,
 
-    pub game_id: VideogameId,
-    pub game_name: String,
-    pub game_slug: String,
-    pub country: Option<String>,
-    pub state: Option<String>,
+    /// The games tracked by the dataset; never empty
+    pub games: Vec<VideogameData>,
+    /// The areas tracked by the dataset, or empty if it is global
+    pub locations: Vec<Location>,
+    pub online: OnlinePolicy,
+    pub rules: EventRules,
 
     pub decay_const: f64,
     pub var_const: f64,
+
+    pub rate_teams: bool,
+    /// Whether sets count for more when won by a larger margin of games
+    pub weight_margin: bool,
 }
 
 /// Return the path to the datasets file.
@@ -29,15 +94,20 @@ fn datasets_path(dir: &Path) -> std::io::Result<PathBuf> {
     path.push("datasets.sqlite");
 
     // Create datasets file if it doesn't exist
-    OpenOptions::new().write(true).create(true).open(&path)?;
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
 
     Ok(path)
 }
 
-pub fn open_datasets(dir: &Path) -> sqlite::Result<Connection> {
-    let path = datasets_path(dir).unwrap();
+// Schema
 
-    let query = "
+/// The tables of the datasets file before the schema was versioned. Files
+/// without a version have this schema, which is version 1.
+const BASELINE_SCHEMA: &str = "
 CREATE TABLE IF NOT EXISTS datasets (
     name TEXT UNIQUE NOT NULL,
     start INTEGER NOT NULL,
@@ -70,11 +140,339 @@ CREATE TABLE IF NOT EXISTS sets (
 ) STRICT, WITHOUT ROWID;
 ";
 
-    let connection = sqlite::open(path)?;
-    connection.execute(query)?;
+/// Steps that upgrade the datasets file from each schema version to the next,
+/// in order. A file at version `n` has had the first `n - 1` steps applied.
+const MIGRATIONS: &[fn(&Connection) -> sqlite::Result<()>] = &[
+    migrate_sync_progress,
+    migrate_set_data,
+    migrate_rate_teams,
+    migrate_set_dqs,
+    migrate_online_policy,
+    migrate_event_rules,
+    migrate_event_filters,
+    migrate_dataset_games,
+    migrate_dataset_locations,
+    migrate_set_scores,
+    migrate_characters,
+    migrate_missing_discrims,
+    migrate_set_order,
+    migrate_tournaments,
+    migrate_set_results,
+];
+
+pub const SCHEMA_VERSION: u64 = MIGRATIONS.len() as u64 + 1;
+
+pub fn open_datasets(dir: &Path) -> sqlite::Result<Connection> {
+    let path = datasets_path(dir).unwrap();
+    let connection = sqlite::open(&path)?;
+
+    let version = match get_schema_version(&connection)? {
+        Some(version) => version,
+        // Files from before the schema was versioned
+        None if table_exists(&connection, "datasets")? => 1,
+        None => {
+            transaction(&connection, || create_schema(&connection))?;
+            return Ok(connection);
+        }
+    };
+
+    if version > SCHEMA_VERSION {
+        return Err(sqlite::Error {
+            code: None,
+            message: Some(format!(
+                "datasets file has schema version {}, but this version of StartRNR only supports up to {}",
+                version, SCHEMA_VERSION
+            )),
+        });
+    }
+
+    if version < SCHEMA_VERSION {
+        backup_datasets(&path, version).map_err(|e| sqlite::Error {
+            code: None,
+            message: Some(format!("could not back up datasets file: {}", e)),
+        })?;
+
+        for (step, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+            transaction(&connection, || {
+                migration(&connection)?;
+                set_schema_version(&connection, step as u64 + 2)
+            })?;
+        }
+    }
+
     Ok(connection)
 }
 
+/// Create the tables of a new datasets file, by applying every migration to the
+/// baseline schema.
+fn create_schema(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(BASELINE_SCHEMA)?;
+    for migration in MIGRATIONS {
+        migration(connection)?;
+    }
+    set_schema_version(connection, SCHEMA_VERSION)
+}
+
+/// Run a function in a transaction, which is committed if the function
+/// succeeds and rolled back if it fails.
+pub fn transaction<T>(
+    connection: &Connection,
+    f: impl FnOnce() -> sqlite::Result<T>,
+) -> sqlite::Result<T> {
+    connection.execute("BEGIN;")?;
+    match f() {
+        Ok(result) => {
+            connection.execute("COMMIT;")?;
+            Ok(result)
+        }
+        Err(err) => {
+            // The original error is more useful than any from rolling back
+            let _ = connection.execute("ROLLBACK;");
+            Err(err)
+        }
+    }
+}
+
+/// Copy the datasets file before it is migrated, so that it can be restored if
+/// anything goes wrong.
+fn backup_datasets(path: &Path, version: u64) -> std::io::Result<()> {
+    let backup = path.with_extension(format!("sqlite.v{}.bak", version));
+    std::fs::copy(path, backup).map(|_| ())
+}
+
+fn table_exists(connection: &Connection, table: &str) -> sqlite::Result<bool> {
+    let mut statement = connection.prepare(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_schema WHERE type = 'table' AND name = ?) AS present",
+    )?;
+    statement.bind((1, table))?;
+    statement.next()?;
+    Ok(statement.read::<i64, _>("present")? != 0)
+}
+
+pub fn get_schema_version(connection: &Connection) -> sqlite::Result<Option<u64>> {
+    if !table_exists(connection, "schema_version")? {
+        return Ok(None);
+    }
+
+    let mut statement = connection.prepare("SELECT version FROM schema_version")?;
+    Ok(match statement.next()? {
+        State::Row => Some(statement.read::<i64, _>("version")? as u64),
+        State::Done => None,
+    })
+}
+
+fn set_schema_version(connection: &Connection, version: u64) -> sqlite::Result<()> {
+    connection.execute(format!(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL) STRICT;
+DELETE FROM schema_version;
+INSERT INTO schema_version VALUES ({});",
+        version
+    ))
+}
+
+// Migrations
+
+/// Version 2: Track which events each dataset has synced, and syncs that were
+/// interrupted.
+fn migrate_sync_progress(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(
+        "CREATE TABLE dataset_events (
+    dataset TEXT NOT NULL,
+    event INTEGER NOT NULL REFERENCES events,
+    PRIMARY KEY (dataset, event)
+) STRICT, WITHOUT ROWID;
+
+CREATE TABLE pending_syncs (
+    dataset TEXT PRIMARY KEY,
+    until INTEGER NOT NULL
+) STRICT;",
+    )
+}
+
+/// Version 3: Store set results, so that datasets can be recomputed.
+fn migrate_set_data(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(
+        "CREATE TABLE set_data (
+    id TEXT PRIMARY KEY REFERENCES sets,
+    time INTEGER NOT NULL,
+    winner INTEGER NOT NULL
+) STRICT;
+
+CREATE TABLE set_players (
+    set_id TEXT NOT NULL REFERENCES sets,
+    team INTEGER NOT NULL,
+    player INTEGER NOT NULL REFERENCES players,
+    PRIMARY KEY (set_id, team, player)
+) STRICT, WITHOUT ROWID;",
+    )
+}
+
+/// Version 4: Datasets can rate team sets.
+fn migrate_rate_teams(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute("ALTER TABLE datasets ADD COLUMN rate_teams INTEGER NOT NULL DEFAULT 0;")
+}
+
+/// Version 5: Record which sets were DQs or forfeits.
+fn migrate_set_dqs(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute("ALTER TABLE set_data ADD COLUMN dq INTEGER NOT NULL DEFAULT 0;")
+}
+
+/// Version 6: Datasets can include only online or offline events.
+fn migrate_online_policy(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute("ALTER TABLE datasets ADD COLUMN online INTEGER;")
+}
+
+/// Version 7: Datasets can filter events by size, type and name.
+fn migrate_event_rules(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(
+        "ALTER TABLE datasets ADD COLUMN min_entrants INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE datasets ADD COLUMN singles_only INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE dataset_keywords (
+    dataset TEXT NOT NULL,
+    keyword TEXT NOT NULL,
+    PRIMARY KEY (dataset, keyword)
+) STRICT, WITHOUT ROWID;",
+    )
+}
+
+/// Version 8: Datasets can include or exclude specific tournaments and events.
+fn migrate_event_filters(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(
+        "CREATE TABLE dataset_filters (
+    dataset TEXT NOT NULL,
+    slug TEXT NOT NULL,
+    include INTEGER NOT NULL,
+    PRIMARY KEY (dataset, slug)
+) STRICT, WITHOUT ROWID;",
+    )
+}
+
+/// Version 9: Datasets can track multiple games.
+fn migrate_dataset_games(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(
+        "CREATE TABLE dataset_games (
+    dataset TEXT NOT NULL,
+    game_id INTEGER NOT NULL,
+    game_name TEXT NOT NULL,
+    game_slug TEXT NOT NULL,
+    PRIMARY KEY (dataset, game_id)
+) STRICT;",
+    )
+}
+
+/// Version 10: Datasets can cover multiple regions and radius-based locations.
+fn migrate_dataset_locations(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(
+        "CREATE TABLE dataset_locations (
+    dataset TEXT NOT NULL,
+    country TEXT,
+    state TEXT,
+    latitude REAL,
+    longitude REAL,
+    distance TEXT
+) STRICT;",
+    )
+}
+
+/// Version 11: Store game scores, which datasets can weight updates by.
+fn migrate_set_scores(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(
+        "ALTER TABLE datasets ADD COLUMN weight_margin INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE set_scores (
+    set_id TEXT NOT NULL REFERENCES sets,
+    team INTEGER NOT NULL,
+    score INTEGER NOT NULL,
+    PRIMARY KEY (set_id, team)
+) STRICT, WITHOUT ROWID;",
+    )
+}
+
+/// Version 12: Store the characters played in each game.
+fn migrate_characters(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(
+        "CREATE TABLE characters (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL
+) STRICT;
+
+CREATE TABLE set_games (
+    set_id TEXT NOT NULL REFERENCES sets,
+    game INTEGER NOT NULL,
+    winner INTEGER,
+    PRIMARY KEY (set_id, game)
+) STRICT, WITHOUT ROWID;
+
+CREATE TABLE game_selections (
+    set_id TEXT NOT NULL,
+    game INTEGER NOT NULL,
+    player INTEGER NOT NULL REFERENCES players,
+    character INTEGER NOT NULL REFERENCES characters,
+    PRIMARY KEY (set_id, game, player, character),
+    FOREIGN KEY (set_id, game) REFERENCES set_games
+) STRICT, WITHOUT ROWID;",
+    )
+}
+
+/// Version 13: Players without a start.gg account have no discriminator.
+fn migrate_missing_discrims(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(
+        "CREATE TABLE players_new (
+    id INTEGER PRIMARY KEY,
+    discrim TEXT UNIQUE,
+    name TEXT NOT NULL,
+    prefix TEXT
+) STRICT;
+INSERT INTO players_new SELECT id, discrim, name, prefix FROM players;
+DROP TABLE players;
+ALTER TABLE players_new RENAME TO players;",
+    )
+}
+
+/// Version 14: Store what sets are rated in order of, and which synced events
+/// are rated.
+fn migrate_set_order(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(
+        "ALTER TABLE set_data ADD COLUMN completed INTEGER;
+ALTER TABLE set_data ADD COLUMN round INTEGER;
+ALTER TABLE dataset_events ADD COLUMN rated INTEGER NOT NULL DEFAULT 1;",
+    )
+}
+
+/// Version 15: Store tournament and event metadata.
+fn migrate_tournaments(connection: &Connection) -> sqlite::Result<()> {
+    connection.execute(
+        "CREATE TABLE tournaments (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    slug TEXT NOT NULL,
+    start INTEGER NOT NULL,
+    city TEXT,
+    state TEXT,
+    country TEXT,
+    online INTEGER NOT NULL,
+    entrants INTEGER NOT NULL
+) STRICT;
+
+ALTER TABLE events ADD COLUMN tournament INTEGER REFERENCES tournaments;
+ALTER TABLE events ADD COLUMN name TEXT;
+ALTER TABLE events ADD COLUMN start INTEGER;
+ALTER TABLE events ADD COLUMN entrants INTEGER;
+ALTER TABLE events ADD COLUMN game INTEGER;
+ALTER TABLE events ADD COLUMN online INTEGER;",
+    )
+}
+
+/// Version 16: Store the set results of each dataset in a table, rather than
+/// as lists of set IDs.
+fn migrate_set_results(connection: &Connection) -> sqlite::Result<()> {
+    for dataset in list_dataset_names(connection)? {
+        migrate_dataset_set_results(connection, &dataset)?;
+    }
+    Ok(())
+}
+
 // TODO: Sanitize dataset names
 
 pub fn list_dataset_names(connection: &Connection) -> sqlite::Result<Vec<String>> {
@@ -87,197 +485,970 @@ pub fn list_dataset_names(connection: &Connection) -> sqlite::Result<Vec<String>
         .try_collect()
 }
 
-pub fn list_datasets(connection: &Connection) -> sqlite::Result<Vec<(String, DatasetMetadata)>> {
-    let query = "SELECT * FROM datasets";
+fn read_metadata(row: &Row) -> DatasetMetadata {
+    DatasetMetadata {
+        start: Timestamp(row.read::<i64, _>("start") as u64),
+        end: row
+            .read::<Option<i64>, _>("end")
+            .map(|x| Timestamp(x as u64)),
+        last_sync: Timestamp(row.read::<i64, _>("last_sync") as u64),
+        // Datasets created before multiple games were supported only store
+        // their game here; any others are filled in separately
+        games: vec![VideogameData {
+            id: VideogameId(row.read::<i64, _>("game_id") as u64),
+            name: row.read::<&str, _>("game_name").to_owned(),
+            slug: row.read::<&str, _>("game_slug").to_owned(),
+        }],
+        // Datasets created before multiple locations were supported only
+        // store their location here; any others are filled in separately
+        locations: row
+            .read::<Option<&str>, _>("country")
+            .map(|country| Location::Region {
+                country: country.to_owned(),
+                state: row.read::<Option<&str>, _>("state").map(String::from),
+            })
+            .into_iter()
+            .collect(),
+        online: OnlinePolicy::from_sql(row.read::<Option<i64>, _>("online")),
+        rules: EventRules {
+            // Filled in separately
+            blocklist: Vec::new(),
+            min_entrants: row.read::<i64, _>("min_entrants") as u64,
+            singles_only: row.read::<i64, _>("singles_only") != 0,
+        },
+        decay_const: row.read::<f64, _>("decay_rate"),
+        var_const: row.read::<f64, _>("var_const"),
+        rate_teams: row.read::<i64, _>("rate_teams") != 0,
+        weight_margin: row.read::<i64, _>("weight_margin") != 0,
+    }
+}
+
+fn get_blocklist(connection: &Connection, dataset: &str) -> sqlite::Result<Vec<String>> {
+    let query = "SELECT keyword FROM dataset_keywords WHERE dataset = ? ORDER BY keyword";
 
     connection
         .prepare(query)?
         .into_iter()
+        .bind((1, dataset))?
+        .map(|r| r.map(|x| x.read::<&str, _>("keyword").to_owned()))
+        .try_collect()
+}
+
+fn get_games(connection: &Connection, dataset: &str) -> sqlite::Result<Vec<VideogameData>> {
+    // The first game added is the dataset's main game
+    let query = "SELECT * FROM dataset_games WHERE dataset = ? ORDER BY rowid";
+
+    connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, dataset))?
         .map(|r| {
             let r_ = r?;
-            Ok((
-                r_.read::<&str, _>("name").to_owned(),
-                DatasetMetadata {
-                    start: Timestamp(r_.read::<i64, _>("start") as u64),
-                    end: r_
-                        .read::<Option<i64>, _>("end")
-                        .map(|x| Timestamp(x as u64)),
-                    last_sync: Timestamp(r_.read::<i64, _>("last_sync") as u64),
-                    game_id: VideogameId(r_.read::<i64, _>("game_id") as u64),
-                    game_name: r_.read::<&str, _>("game_name").to_owned(),
-                    game_slug: r_.read::<&str, _>("game_slug").to_owned(),
-                    country: r_.read::<Option<&str>, _>("country").map(String::from),
+            Ok(VideogameData {
+                id: VideogameId(r_.read::<i64, _>("game_id") as u64),
+                name: r_.read::<&str, _>("game_name").to_owned(),
+                slug: r_.read::<&str, _>("game_slug").to_owned(),
+            })
+        })
+        .try_collect()
+}
+
+fn get_locations(connection: &Connection, dataset: &str) -> sqlite::Result<Vec<Location>> {
+    let query = "SELECT * FROM dataset_locations WHERE dataset = ? ORDER BY rowid";
+
+    connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, dataset))?
+        .map(|r| {
+            let r_ = r?;
+            Ok(match r_.read::<Option<&str>, _>("country") {
+                Some(country) => Location::Region {
+                    country: country.to_owned(),
                     state: r_.read::<Option<&str>, _>("state").map(String::from),
-                    decay_const: r_.read::<f64, _>("decay_rate"),
-                    var_const: r_.read::<f64, _>("adj_decay_rate"),
                 },
-            ))
+                None => Location::Radius {
+                    latitude: r_.read::<f64, _>("latitude"),
+                    longitude: r_.read::<f64, _>("longitude"),
+                    distance: r_.read::<&str, _>("distance").to_owned(),
+                },
+            })
         })
         .try_collect()
 }
 
+/// Fill in the parts of a dataset's metadata stored outside of the datasets
+/// table.
+fn read_side_tables(
+    connection: &Connection,
+    dataset: &str,
+    metadata: &mut DatasetMetadata,
+) -> sqlite::Result<()> {
+    metadata.rules.blocklist = get_blocklist(connection, dataset)?;
+
+    let games = get_games(connection, dataset)?;
+    if !games.is_empty() {
+        metadata.games = games;
+    }
+
+    let locations = get_locations(connection, dataset)?;
+    if !locations.is_empty() {
+        metadata.locations = locations;
+    }
+    Ok(())
+}
+
+pub fn list_datasets(connection: &Connection) -> sqlite::Result<Vec<(String, DatasetMetadata)>> {
+    let query = "SELECT * FROM datasets";
+
+    let mut datasets = connection
+        .prepare(query)?
+        .into_iter()
+        .map(|r| -> sqlite::Result<_> {
+            let r_ = r?;
+            Ok((r_.read::<&str, _>("name").to_owned(), read_metadata(&r_)))
+        })
+        .try_collect::<Vec<_>>()?;
+
+    for (name, metadata) in datasets.iter_mut() {
+        read_side_tables(connection, name, metadata)?;
+    }
+    Ok(datasets)
+}
+
 pub fn delete_dataset(connection: &Connection, dataset: &str) -> sqlite::Result<()> {
     let query = format!(
         r#"DELETE FROM datasets WHERE name = '{0}';
+        DELETE FROM dataset_events WHERE dataset = '{0}';
+        DELETE FROM pending_syncs WHERE dataset = '{0}';
+        DELETE FROM dataset_keywords WHERE dataset = '{0}';
+        DELETE FROM dataset_filters WHERE dataset = '{0}';
+        DELETE FROM dataset_games WHERE dataset = '{0}';
+        DELETE FROM dataset_locations WHERE dataset = '{0}';
         DROP TABLE "{0}_players";
-        DROP TABLE "{0}_network";"#,
+        DROP TABLE "{0}_network";
+        DROP TABLE "{0}_set_results";"#,
+        dataset
+    );
+
+    connection.execute(query)
+}
+
+pub fn rename_dataset(connection: &Connection, old: &str, new: &str) -> sqlite::Result<()> {
+    if old == new {
+        return Ok(());
+    }
+
+    let query = format!(
+        r#"UPDATE datasets SET name = '{1}' WHERE name = '{0}';
+UPDATE dataset_events SET dataset = '{1}' WHERE dataset = '{0}';
+UPDATE pending_syncs SET dataset = '{1}' WHERE dataset = '{0}';
+UPDATE dataset_keywords SET dataset = '{1}' WHERE dataset = '{0}';
+UPDATE dataset_filters SET dataset = '{1}' WHERE dataset = '{0}';
+UPDATE dataset_games SET dataset = '{1}' WHERE dataset = '{0}';
+UPDATE dataset_locations SET dataset = '{1}' WHERE dataset = '{0}';
+ALTER TABLE "{0}_players" RENAME TO "{1}_players";
+ALTER TABLE "{0}_network" RENAME TO "{1}_network";
+ALTER TABLE "{0}_set_results" RENAME TO "{1}_set_results";
+DROP INDEX "{0}_network_B";
+CREATE INDEX "{1}_network_B" ON "{1}_network" (player_B);
+DROP INDEX "{0}_set_results_winner";
+CREATE INDEX "{1}_set_results_winner" ON "{1}_set_results" (winner, loser, time);
+DROP INDEX "{0}_set_results_loser";
+CREATE INDEX "{1}_set_results_loser" ON "{1}_set_results" (loser, time);"#,
+        old, new
+    );
+
+    connection.execute(query)
+}
+
+fn create_dataset_tables(connection: &Connection, dataset: &str) -> sqlite::Result<()> {
+    let query = format!(
+        r#"CREATE TABLE "{0}_players" (
+    id INTEGER PRIMARY KEY REFERENCES players
+) STRICT;
+
+CREATE TABLE "{0}_network" (
+    player_A INTEGER NOT NULL,
+    player_B INTEGER NOT NULL,
+    advantage REAL NOT NULL,
+    variance REAL NOT NULL,
+    last_updated INTEGER NOT NULL,
+
+    PRIMARY KEY (player_A, player_B),
+    CHECK (player_A < player_B),
+    FOREIGN KEY(player_A) REFERENCES "{0}_players"
+        ON DELETE CASCADE,
+    FOREIGN KEY(player_B) REFERENCES "{0}_players"
+        ON DELETE CASCADE
+) STRICT;
+CREATE INDEX "{0}_network_B" ON "{0}_network" (player_B);"#,
+        dataset
+    );
+
+    connection.execute(query)?;
+    create_set_results_table(connection, dataset)
+}
+
+// A team set has one result for each pair of opposing players
+fn create_set_results_table(connection: &Connection, dataset: &str) -> sqlite::Result<()> {
+    let query = format!(
+        r#"CREATE TABLE "{0}_set_results" (
+    set_id TEXT NOT NULL,
+    event INTEGER,
+    time INTEGER,
+    winner INTEGER NOT NULL,
+    loser INTEGER NOT NULL,
+    winner_score INTEGER,
+    loser_score INTEGER,
+    -- Change in the winner's advantage over the loser; unknown for sets rated
+    -- by older versions
+    delta REAL,
+
+    PRIMARY KEY (set_id, winner, loser),
+    FOREIGN KEY(winner) REFERENCES "{0}_players"
+        ON DELETE CASCADE,
+    FOREIGN KEY(loser) REFERENCES "{0}_players"
+        ON DELETE CASCADE
+) STRICT, WITHOUT ROWID;
+CREATE INDEX "{0}_set_results_winner" ON "{0}_set_results" (winner, loser, time);
+CREATE INDEX "{0}_set_results_loser" ON "{0}_set_results" (loser, time);"#,
         dataset
     );
 
     connection.execute(query)
 }
 
-pub fn rename_dataset(connection: &Connection, old: &str, new: &str) -> sqlite::Result<()> {
-    if old == new {
-        return Ok(());
-    }
-
+/// Move the set results of a dataset created by an older version, which were
+/// stored as `;`-separated lists of set IDs, into its set results table.
+fn migrate_dataset_set_results(connection: &Connection, dataset: &str) -> sqlite::Result<()> {
+    create_set_results_table(connection, dataset)?;
+
+    let query1 = format!(
+        r#"SELECT player_A, player_B, sets_A, sets_B FROM "{}_network""#,
+        dataset
+    );
+    let query2 = format!(
+        r#"INSERT OR IGNORE INTO "{}_set_results" (set_id, event, time, winner, loser)
+    VALUES (:set,
+        (SELECT event FROM sets WHERE id = :set),
+        (SELECT coalesce(completed, time) FROM set_data WHERE id = :set),
+        :winner, :loser)"#,
+        dataset
+    );
+
+    let edges = connection
+        .prepare(query1)?
+        .into_iter()
+        .map(|r| -> sqlite::Result<_> {
+            let r_ = r?;
+            Ok((
+                r_.read::<i64, _>("player_A"),
+                r_.read::<i64, _>("player_B"),
+                r_.read::<&str, _>("sets_A").to_owned(),
+                r_.read::<&str, _>("sets_B").to_owned(),
+            ))
+        })
+        .try_collect::<Vec<_>>()?;
+
+    for (player_a, player_b, sets_a, sets_b) in edges {
+        let results = sets_a
+            .split_terminator(';')
+            .map(|set| (set, player_a, player_b))
+            .chain(
+                sets_b
+                    .split_terminator(';')
+                    .map(|set| (set, player_b, player_a)),
+            );
+        for (set, winner, loser) in results {
+            connection
+                .prepare(&query2)?
+                .into_iter()
+                .bind((":set", set))?
+                .bind((":winner", winner))?
+                .bind((":loser", loser))?
+                .try_for_each(|x| x.map(|_| ()))?;
+        }
+    }
+
+    // Rebuild the old tables without their set lists
+    let query3 = format!(
+        r#"CREATE TABLE "{0}_players_new" (
+    id INTEGER PRIMARY KEY REFERENCES players
+) STRICT;
+INSERT INTO "{0}_players_new" SELECT id FROM "{0}_players";
+
+CREATE TABLE "{0}_network_new" (
+    player_A INTEGER NOT NULL,
+    player_B INTEGER NOT NULL,
+    advantage REAL NOT NULL,
+    variance REAL NOT NULL,
+    last_updated INTEGER NOT NULL,
+
+    PRIMARY KEY (player_A, player_B),
+    CHECK (player_A < player_B),
+    FOREIGN KEY(player_A) REFERENCES "{0}_players"
+        ON DELETE CASCADE,
+    FOREIGN KEY(player_B) REFERENCES "{0}_players"
+        ON DELETE CASCADE
+) STRICT;
+INSERT INTO "{0}_network_new"
+    SELECT player_A, player_B, advantage, variance, last_updated FROM "{0}_network";
+
+DROP TABLE "{0}_network";
+DROP TABLE "{0}_players";
+ALTER TABLE "{0}_players_new" RENAME TO "{0}_players";
+ALTER TABLE "{0}_network_new" RENAME TO "{0}_network";
+CREATE INDEX "{0}_network_B" ON "{0}_network" (player_B);"#,
+        dataset
+    );
+    connection.execute(query3)
+}
+
+pub fn new_dataset(
+    connection: &Connection,
+    dataset: &str,
+    metadata: DatasetMetadata,
+) -> sqlite::Result<()> {
+    let query = r#"INSERT INTO datasets
+    (name, start, end, last_sync, game_id, game_name, game_slug,
+        decay_rate, var_const, rate_teams, online, weight_margin)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#;
+
+    connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, dataset))?
+        .bind((2, metadata.start.0 as i64))?
+        .bind((3, metadata.end.map(|x| x.0 as i64)))?
+        .bind((4, metadata.last_sync.0 as i64))?
+        .bind((5, metadata.games[0].id.0 as i64))?
+        .bind((6, &metadata.games[0].name[..]))?
+        .bind((7, &metadata.games[0].slug[..]))?
+        .bind((8, metadata.decay_const))?
+        .bind((9, metadata.var_const))?
+        .bind((10, metadata.rate_teams as i64))?
+        .bind((11, metadata.online.to_sql()))?
+        .bind((12, metadata.weight_margin as i64))?
+        .try_for_each(|x| x.map(|_| ()))?;
+
+    let query2 = "INSERT INTO dataset_games (dataset, game_id, game_name, game_slug)
+    VALUES (?, ?, ?, ?)";
+
+    metadata.games.iter().try_for_each(|game| {
+        let mut statement = connection.prepare(query2)?;
+        statement.bind((1, dataset))?;
+        statement.bind((2, game.id.0 as i64))?;
+        statement.bind((3, &game.name[..]))?;
+        statement.bind((4, &game.slug[..]))?;
+        statement.into_iter().try_for_each(|x| x.map(|_| ()))
+    })?;
+
+    let query3 = "INSERT INTO dataset_locations
+    (dataset, country, state, latitude, longitude, distance) VALUES (?, ?, ?, ?, ?, ?)";
+
+    metadata.locations.iter().try_for_each(|location| {
+        let (country, state, latitude, longitude, distance) = match location {
+            Location::Region { country, state } => {
+                (Some(&country[..]), state.as_deref(), None, None, None)
+            }
+            Location::Radius {
+                latitude,
+                longitude,
+                distance,
+            } => (
+                None,
+                None,
+                Some(*latitude),
+                Some(*longitude),
+                Some(&distance[..]),
+            ),
+        };
+
+        let mut statement = connection.prepare(query3)?;
+        statement.bind((1, dataset))?;
+        statement.bind((2, country))?;
+        statement.bind((3, state))?;
+        statement.bind((4, latitude))?;
+        statement.bind((5, longitude))?;
+        statement.bind((6, distance))?;
+        statement.into_iter().try_for_each(|x| x.map(|_| ()))
+    })?;
+
+    set_event_rules(connection, dataset, &metadata.rules)?;
+    create_dataset_tables(connection, dataset)
+}
+
+pub fn set_event_rules(
+    connection: &Connection,
+    dataset: &str,
+    rules: &EventRules,
+) -> sqlite::Result<()> {
+    let query1 = "UPDATE datasets SET min_entrants = :min, singles_only = :singles
+    WHERE name = :dataset";
+    let query2 = "DELETE FROM dataset_keywords WHERE dataset = ?";
+    let query3 = "INSERT OR IGNORE INTO dataset_keywords (dataset, keyword) VALUES (?, ?)";
+
+    connection
+        .prepare(query1)?
+        .into_iter()
+        .bind((":min", rules.min_entrants as i64))?
+        .bind((":singles", rules.singles_only as i64))?
+        .bind((":dataset", dataset))?
+        .try_for_each(|x| x.map(|_| ()))?;
+
+    let mut statement = connection.prepare(query2)?;
+    statement.bind((1, dataset))?;
+    statement.into_iter().try_for_each(|x| x.map(|_| ()))?;
+
+    rules.blocklist.iter().try_for_each(|keyword| {
+        let mut statement = connection.prepare(query3)?;
+        statement.bind((1, dataset))?;
+        statement.bind((2, &keyword[..]))?;
+        statement.into_iter().try_for_each(|x| x.map(|_| ()))
+    })
+}
+
+/// Return the tournaments and events explicitly included in or excluded from a
+/// dataset, as pairs of a slug and whether it is included.
+pub fn get_event_filters(
+    connection: &Connection,
+    dataset: &str,
+) -> sqlite::Result<Vec<(String, bool)>> {
+    let query = "SELECT slug, include FROM dataset_filters WHERE dataset = ? ORDER BY slug";
+
+    connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, dataset))?
+        .map(|r| {
+            let r_ = r?;
+            Ok((
+                r_.read::<&str, _>("slug").to_owned(),
+                r_.read::<i64, _>("include") != 0,
+            ))
+        })
+        .try_collect()
+}
+
+/// Include or exclude a tournament or event from a dataset.
+pub fn set_event_filter(
+    connection: &Connection,
+    dataset: &str,
+    slug: &str,
+    include: bool,
+) -> sqlite::Result<()> {
+    let query = "INSERT OR REPLACE INTO dataset_filters (dataset, slug, include)
+    VALUES (?, ?, ?)";
+
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, dataset))?;
+    statement.bind((2, slug))?;
+    statement.bind((3, include as i64))?;
+    statement.into_iter().try_for_each(|x| x.map(|_| ()))
+}
+
+/// Remove a dataset's rule for a tournament or event, if it is of the given
+/// kind. Returns whether a rule was removed.
+pub fn remove_event_filter(
+    connection: &Connection,
+    dataset: &str,
+    slug: &str,
+    include: bool,
+) -> sqlite::Result<bool> {
+    let query = "DELETE FROM dataset_filters WHERE dataset = ? AND slug = ? AND include = ?";
+
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, dataset))?;
+    statement.bind((2, slug))?;
+    statement.bind((3, include as i64))?;
+    statement.into_iter().try_for_each(|x| x.map(|_| ()))?;
+    Ok(connection.change_count() > 0)
+}
+
+/// Delete all rating data of a dataset, leaving it as if it was newly created.
+pub fn reset_dataset(connection: &Connection, dataset: &str) -> sqlite::Result<()> {
+    let query = format!(
+        r#"DROP TABLE "{0}_network";
+DROP TABLE "{0}_players";
+DROP TABLE "{0}_set_results";"#,
+        dataset
+    );
+
+    connection.execute(query)?;
+    create_dataset_tables(connection, dataset)
+}
+
+pub fn update_parameters(
+    connection: &Connection,
+    dataset: &str,
+    decay_const: f64,
+    var_const: f64,
+    weight_margin: bool,
+) -> sqlite::Result<()> {
+    let query = "UPDATE datasets SET decay_rate = :d, var_const = :v, weight_margin = :m
+    WHERE name = :dataset";
+
+    connection
+        .prepare(query)?
+        .into_iter()
+        .bind((":d", decay_const))?
+        .bind((":v", var_const))?
+        .bind((":m", weight_margin as i64))?
+        .bind((":dataset", dataset))?
+        .try_for_each(|x| x.map(|_| ()))
+}
+
+pub fn get_metadata(
+    connection: &Connection,
+    dataset: &str,
+) -> sqlite::Result<Option<DatasetMetadata>> {
+    let query = "SELECT * FROM datasets WHERE name = ?";
+
+    let metadata = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, dataset))?
+        .next()
+        .map(|r| r.map(|r_| read_metadata(&r_)))
+        .transpose()?;
+
+    metadata
+        .map(|mut metadata| {
+            read_side_tables(connection, dataset, &mut metadata)?;
+            Ok(metadata)
+        })
+        .transpose()
+}
+
+pub fn update_last_sync(
+    connection: &Connection,
+    dataset: &str,
+    current_time: Timestamp,
+) -> sqlite::Result<()> {
+    let query = "UPDATE datasets SET last_sync = :sync WHERE name = :dataset";
+
+    connection
+        .prepare(query)?
+        .into_iter()
+        .bind((":sync", current_time.0 as i64))?
+        .bind((":dataset", dataset))?
+        .try_for_each(|x| x.map(|_| ()))
+}
+
+// Sync progress
+
+pub fn get_pending_sync(
+    connection: &Connection,
+    dataset: &str,
+) -> sqlite::Result<Option<Timestamp>> {
+    let query = "SELECT until FROM pending_syncs WHERE dataset = ?";
+
+    connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, dataset))?
+        .next()
+        .map(|r| r.map(|r_| Timestamp(r_.read::<i64, _>("until") as u64)))
+        .transpose()
+}
+
+pub fn set_pending_sync(
+    connection: &Connection,
+    dataset: &str,
+    until: Timestamp,
+) -> sqlite::Result<()> {
+    let query = "INSERT OR REPLACE INTO pending_syncs (dataset, until) VALUES (?, ?)";
+
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, dataset))?;
+    statement.bind((2, until.0 as i64))?;
+    statement.into_iter().try_for_each(|x| x.map(|_| ()))
+}
+
+pub fn event_synced(
+    connection: &Connection,
+    dataset: &str,
+    event: EventId,
+) -> sqlite::Result<bool> {
+    let query =
+        "SELECT EXISTS(SELECT 1 FROM dataset_events WHERE dataset = ? AND event = ?) AS synced";
+
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, dataset))?;
+    statement.bind((2, event.0 as i64))?;
+    statement.next()?;
+    Ok(statement.read::<i64, _>("synced")? != 0)
+}
+
+/// Record that an event's sets have been stored for a dataset. If `rated` is
+/// false, the sets have not yet been used to update the dataset's ratings.
+pub fn mark_event_synced(
+    connection: &Connection,
+    dataset: &str,
+    event: EventId,
+    rated: bool,
+) -> sqlite::Result<()> {
+    let query = "INSERT OR IGNORE INTO dataset_events (dataset, event, rated) VALUES (?, ?, ?)";
+
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, dataset))?;
+    statement.bind((2, event.0 as i64))?;
+    statement.bind((3, rated as i64))?;
+    statement.into_iter().try_for_each(|x| x.map(|_| ()))
+}
+
+/// Record that the sets of every event in a dataset have been rated.
+pub fn mark_events_rated(connection: &Connection, dataset: &str) -> sqlite::Result<()> {
+    let query = "UPDATE dataset_events SET rated = 1 WHERE dataset = ?";
+
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, dataset))?;
+    statement.into_iter().try_for_each(|x| x.map(|_| ()))
+}
+
+/// Mark a sync as complete, advancing the last sync time of the dataset.
+///
+/// Both changes are made in one transaction, so that `last_sync` is never
+/// advanced past a sync that has not been fully committed.
+pub fn finish_sync(connection: &Connection, dataset: &str, until: Timestamp) -> sqlite::Result<()> {
+    let query = "DELETE FROM pending_syncs WHERE dataset = ?";
+
+    transaction(connection, || {
+        update_last_sync(connection, dataset, until)?;
+        let mut statement = connection.prepare(query)?;
+        statement.bind((1, dataset))?;
+        statement.into_iter().try_for_each(|x| x.map(|_| ()))
+    })
+}
+
+// Database Updating
+
+/// Store an event and its tournament (if known), replacing any older
+/// information about either.
+pub fn add_event(connection: &Connection, event: &EventData) -> sqlite::Result<()> {
+    let query1 = "INSERT OR REPLACE INTO tournaments
+    (id, name, slug, start, city, state, country, online, entrants)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)";
+    let query2 = "INSERT INTO events (id, slug, tournament, name, start, entrants, game, online)
+    VALUES (:id, :slug, :tournament, :name, :start, :entrants, :game, :online)
+    ON CONFLICT (id) DO UPDATE SET slug = :slug,
+        tournament = coalesce(:tournament, tournament), name = :name,
+        start = :start, entrants = :entrants, game = :game, online = :online";
+
+    if let Some(tournament) = &event.tournament {
+        let mut statement = connection.prepare(query1)?;
+        statement.bind((1, tournament.id.0 as i64))?;
+        statement.bind((2, &tournament.name[..]))?;
+        statement.bind((3, &tournament.slug[..]))?;
+        statement.bind((4, tournament.time.0 as i64))?;
+        statement.bind((5, tournament.city.as_deref()))?;
+        statement.bind((6, tournament.state.as_deref()))?;
+        statement.bind((7, tournament.country.as_deref()))?;
+        statement.bind((8, tournament.online as i64))?;
+        statement.bind((9, tournament.entrants as i64))?;
+        statement.into_iter().try_for_each(|x| x.map(|_| ()))?;
+    }
+
+    connection
+        .prepare(query2)?
+        .into_iter()
+        .bind((":id", event.id.0 as i64))?
+        .bind((":slug", &event.slug[..]))?
+        .bind((
+            ":tournament",
+            event.tournament.as_ref().map(|tour| tour.id.0 as i64),
+        ))?
+        .bind((":name", &event.name[..]))?
+        .bind((":start", event.time.0 as i64))?
+        .bind((":entrants", event.entrants as i64))?
+        .bind((":game", event.game.map(|game| game.0 as i64)))?
+        .bind((":online", event.online as i64))?
+        .try_for_each(|x| x.map(|_| ()))
+}
+
+pub fn add_set(connection: &Connection, set: &SetId, event: EventId) -> sqlite::Result<()> {
+    let query = "INSERT OR IGNORE INTO sets (id, event) VALUES (?, ?)";
+
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, &set.0.to_string()[..]))?;
+    statement.bind((2, event.0 as i64))?;
+    statement.into_iter().try_for_each(|x| x.map(|_| ()))
+}
+
+/// Store the results of a set, so that they can be replayed without accessing
+/// start.gg. Results already stored for the set are replaced.
+pub fn add_set_data(connection: &Connection, set: &SetData, time: Timestamp) -> sqlite::Result<()> {
+    let query1 = "INSERT INTO set_data (id, time, winner, dq, completed, round)
+    VALUES (?, ?, ?, ?, ?, ?)
+    ON CONFLICT (id) DO UPDATE SET time = excluded.time, winner = excluded.winner,
+    dq = excluded.dq, completed = excluded.completed, round = excluded.round";
+    let query2 = "INSERT OR IGNORE INTO players (id, discrim, name, prefix) VALUES (?, ?, ?, ?)";
+    // Players may link a start.gg account after they were first seen
+    let query2b = "UPDATE OR IGNORE players SET discrim = ? WHERE id = ? AND discrim IS NULL";
+    let query3 = "INSERT OR IGNORE INTO set_players (set_id, team, player) VALUES (?, ?, ?)";
+    let query4 = "INSERT INTO set_scores (set_id, team, score) VALUES (?, ?, ?)
+    ON CONFLICT (set_id, team) DO UPDATE SET score = excluded.score";
+    let query5 = "INSERT INTO set_games (set_id, game, winner) VALUES (?, ?, ?)
+    ON CONFLICT (set_id, game) DO UPDATE SET winner = excluded.winner";
+    let query6 = "INSERT OR REPLACE INTO characters (id, name) VALUES (?, ?)";
+    let query7 = "INSERT OR IGNORE INTO game_selections (set_id, game, player, character)
+    VALUES (?, ?, ?, ?)";
+
+    let set_id = set.id.0.to_string();
+
+    let mut statement = connection.prepare(query1)?;
+    statement.bind((1, &set_id[..]))?;
+    statement.bind((2, time.0 as i64))?;
+    statement.bind((3, set.winner as i64))?;
+    statement.bind((4, set.dq as i64))?;
+    statement.bind((5, set.completed.map(|t| t.0 as i64)))?;
+    statement.bind((6, set.round))?;
+    statement.into_iter().try_for_each(|x| x.map(|_| ()))?;
+
+    set.scores
+        .iter()
+        .flatten()
+        .enumerate()
+        .try_for_each(|(team, &score)| {
+            let mut statement = connection.prepare(query4)?;
+            statement.bind((1, &set_id[..]))?;
+            statement.bind((2, team as i64))?;
+            statement.bind((3, score as i64))?;
+            statement.into_iter().try_for_each(|x| x.map(|_| ()))
+        })?;
+
+    set.games.iter().try_for_each(|game| {
+        let mut statement = connection.prepare(query5)?;
+        statement.bind((1, &set_id[..]))?;
+        statement.bind((2, game.number as i64))?;
+        statement.bind((3, game.winner.map(|x| x as i64)))?;
+        statement.into_iter().try_for_each(|x| x.map(|_| ()))?;
+
+        game.selections.iter().try_for_each(|(player, character)| {
+            let mut statement = connection.prepare(query6)?;
+            statement.bind((1, character.id.0 as i64))?;
+            statement.bind((2, &character.name[..]))?;
+            statement.into_iter().try_for_each(|x| x.map(|_| ()))?;
+
+            statement = connection.prepare(query7)?;
+            statement.bind((1, &set_id[..]))?;
+            statement.bind((2, game.number as i64))?;
+            statement.bind((3, player.0 as i64))?;
+            statement.bind((4, character.id.0 as i64))?;
+            statement.into_iter().try_for_each(|x| x.map(|_| ()))
+        })
+    })?;
+
+    set.teams
+        .iter()
+        .enumerate()
+        .try_for_each(|(team, players)| {
+            players.iter().try_for_each(|player| {
+                let mut statement = connection.prepare(query2)?;
+                statement.bind((1, player.id.0 as i64))?;
+                statement.bind((2, player.discrim.as_deref()))?;
+                statement.bind((3, &player.name[..]))?;
+                statement.bind((4, player.prefix.as_deref()))?;
+                statement.into_iter().try_for_each(|x| x.map(|_| ()))?;
+
+                if let Some(discrim) = &player.discrim {
+                    statement = connection.prepare(query2b)?;
+                    statement.bind((1, &discrim[..]))?;
+                    statement.bind((2, player.id.0 as i64))?;
+                    statement.into_iter().try_for_each(|x| x.map(|_| ()))?;
+                }
+
+                statement = connection.prepare(query3)?;
+                statement.bind((1, &set_id[..]))?;
+                statement.bind((2, team as i64))?;
+                statement.bind((3, player.id.0 as i64))?;
+                statement.into_iter().try_for_each(|x| x.map(|_| ()))
+            })
+        })
+}
+
+/// Return the players who won a set as it was rated in a dataset, or `None` if
+/// the set has not been rated in the dataset.
+pub fn get_set_winners(
+    connection: &Connection,
+    dataset: &str,
+    set: &SetId,
+) -> sqlite::Result<Option<Vec<PlayerId>>> {
     let query = format!(
-        r#"UPDATE datasets SET name = '{1}' WHERE name = '{0}';
-ALTER TABLE "{0}_players" RENAME TO "{1}_players";
-ALTER TABLE "{0}_network" RENAME TO "{1}_network";
-DROP INDEX "{0}_network_B";
-CREATE INDEX "{1}_network_B" ON "{1}_network" (player_B);"#,
-        old, new
+        r#"SELECT DISTINCT winner FROM "{}_set_results" WHERE set_id = ? ORDER BY winner"#,
+        dataset
     );
 
-    connection.execute(query)
+    let winners = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, &set.0.to_string()[..]))?
+        .map(|r| r.map(|r_| PlayerId(r_.read::<i64, _>("winner") as u64)))
+        .try_collect::<Vec<_>>()?;
+
+    Ok((!winners.is_empty()).then_some(winners))
 }
 
-pub fn new_dataset(
-    connection: &Connection,
-    dataset: &str,
-    metadata: DatasetMetadata,
-) -> sqlite::Result<()> {
-    let query1 = r#"INSERT INTO datasets VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#;
-    let query2 = format!(
-        r#"CREATE TABLE "{0}_players" (
-    id INTEGER PRIMARY KEY REFERENCES players,
-
-    sets_won TEXT NOT NULL DEFAULT '',
-    sets_count_won INTEGER AS (length(sets_won) - length(replace(sets_won, ';', ''))),
-    sets_lost TEXT NOT NULL DEFAULT '',
-    sets_count_lost INTEGER AS (length(sets_lost) - length(replace(sets_lost, ';', ''))),
-    sets TEXT AS (sets_won || sets_lost),
-    sets_count INTEGER AS (sets_count_won + sets_count_lost)
-) STRICT;
+fn get_set_teams(connection: &Connection, set_id: &str) -> sqlite::Result<Teams<PlayerData>> {
+    let query = "SELECT team, player FROM set_players WHERE set_id = ? ORDER BY team, player";
 
-CREATE TABLE "{0}_network" (
-    player_A INTEGER NOT NULL,
-    player_B INTEGER NOT NULL,
-    advantage REAL NOT NULL,
-    variance REAL NOT NULL,
-    last_updated INTEGER NOT NULL,
+    let rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, set_id))?
+        .map(|r| -> sqlite::Result<_> {
+            let r_ = r?;
+            Ok((
+                r_.read::<i64, _>("team") as usize,
+                PlayerId(r_.read::<i64, _>("player") as u64),
+            ))
+        })
+        .try_collect::<Vec<_>>()?;
 
-    sets_A TEXT NOT NULL DEFAULT '',
-    sets_count_A INTEGER AS (length(sets_A) - length(replace(sets_A, ';', ''))),
-    sets_B TEXT NOT NULL DEFAULT '',
-    sets_count_B INTEGER AS (length(sets_B) - length(replace(sets_B, ';', ''))),
-    sets TEXT AS (sets_A || sets_B),
-    sets_count INTEGER AS (sets_count_A + sets_count_B),
+    let mut teams: Teams<PlayerData> = Vec::new();
+    for (team, player) in rows {
+        if teams.len() <= team {
+            teams.resize_with(team + 1, Vec::new);
+        }
+        teams[team].push(get_player(connection, player)?);
+    }
+    Ok(teams)
+}
 
-    PRIMARY KEY (player_A, player_B),
-    CHECK (player_A < player_B),
-    FOREIGN KEY(player_A) REFERENCES "{0}_players"
-        ON DELETE CASCADE,
-    FOREIGN KEY(player_B) REFERENCES "{0}_players"
-        ON DELETE CASCADE
-) STRICT;
-CREATE INDEX "{0}_network_B" ON "{0}_network" (player_B);"#,
-        dataset
-    );
+fn get_set_scores(connection: &Connection, set_id: &str) -> sqlite::Result<Option<Vec<u64>>> {
+    let query = "SELECT score FROM set_scores WHERE set_id = ? ORDER BY team";
 
-    connection
-        .prepare(query1)?
+    let scores = connection
+        .prepare(query)?
         .into_iter()
-        .bind((1, dataset))?
-        .bind((2, metadata.start.0 as i64))?
-        .bind((3, metadata.end.map(|x| x.0 as i64)))?
-        .bind((4, metadata.last_sync.0 as i64))?
-        .bind((5, metadata.game_id.0 as i64))?
-        .bind((6, &metadata.game_name[..]))?
-        .bind((7, &metadata.game_slug[..]))?
-        .bind((8, metadata.country.as_deref()))?
-        .bind((9, metadata.state.as_deref()))?
-        .bind((10, metadata.decay_const))?
-        .bind((11, metadata.var_const))?
-        .try_for_each(|x| x.map(|_| ()))?;
+        .bind((1, set_id))?
+        .map(|r| r.map(|r_| r_.read::<i64, _>("score") as u64))
+        .try_collect::<Vec<_>>()?;
 
-    connection.execute(query2)
+    Ok(Some(scores).filter(|scores| !scores.is_empty()))
 }
 
-pub fn get_metadata(
+/// Return every locally stored set of a dataset along with the slug of its
+/// event, in chronological order.
+pub fn get_dataset_sets(
     connection: &Connection,
     dataset: &str,
-) -> sqlite::Result<Option<DatasetMetadata>> {
-    let query = "SELECT * FROM datasets WHERE name = ?";
+) -> sqlite::Result<Vec<(String, SetData)>> {
+    read_dataset_sets(connection, dataset, false)
+}
 
-    Ok(connection
-        .prepare(query)?
-        .into_iter()
-        .bind((1, dataset))?
-        .next()
-        .map(|r| {
-            let r_ = r?;
-            Ok(DatasetMetadata {
-                start: Timestamp(r_.read::<i64, _>("start") as u64),
-                end: r_
-                    .read::<Option<i64>, _>("end")
-                    .map(|x| Timestamp(x as u64)),
-                last_sync: Timestamp(r_.read::<i64, _>("last_sync") as u64),
-                game_id: VideogameId(r_.read::<i64, _>("game_id") as u64),
-                game_name: r_.read::<&str, _>("game_name").to_owned(),
-                game_slug: r_.read::<&str, _>("game_slug").to_owned(),
-                country: r_.read::<Option<&str>, _>("country").map(String::from),
-                state: r_.read::<Option<&str>, _>("state").map(String::from),
-                decay_const: r_.read::<f64, _>("decay_rate"),
-                var_const: r_.read::<f64, _>("var_const"),
-            })
-        })
-        .and_then(Result::ok))
+/// Return the stored sets of the events in a dataset that have not been rated.
+pub fn get_unrated_sets(
+    connection: &Connection,
+    dataset: &str,
+) -> sqlite::Result<Vec<(String, SetData)>> {
+    read_dataset_sets(connection, dataset, true)
 }
 
-pub fn update_last_sync(
+fn read_dataset_sets(
     connection: &Connection,
     dataset: &str,
-    current_time: Timestamp,
-) -> sqlite::Result<()> {
-    let query = "UPDATE datasets SET last_sync = :sync WHERE name = :dataset";
+    unrated: bool,
+) -> sqlite::Result<Vec<(String, SetData)>> {
+    let query = format!(
+        "SELECT set_data.id, set_data.time, set_data.winner, set_data.dq,
+        set_data.completed, set_data.round, events.slug
+    FROM dataset_events
+    JOIN events ON events.id = dataset_events.event
+    JOIN sets ON sets.event = dataset_events.event
+    JOIN set_data ON set_data.id = sets.id
+    WHERE dataset_events.dataset = ?{}
+    ORDER BY set_data.time, set_data.rowid",
+        if unrated {
+            " AND dataset_events.rated = 0"
+        } else {
+            ""
+        }
+    );
 
-    connection
+    let rows = connection
         .prepare(query)?
         .into_iter()
-        .bind((":sync", current_time.0 as i64))?
-        .bind((":dataset", dataset))?
-        .try_for_each(|x| x.map(|_| ()))
-}
+        .bind((1, dataset))?
+        .map(|r| -> sqlite::Result<_> {
+            let r_ = r?;
+            Ok((
+                r_.read::<&str, _>("id").to_owned(),
+                Timestamp(r_.read::<i64, _>("time") as u64),
+                r_.read::<i64, _>("winner") as usize,
+                r_.read::<i64, _>("dq") != 0,
+                r_.read::<Option<i64>, _>("completed")
+                    .map(|t| Timestamp(t as u64)),
+                r_.read::<Option<i64>, _>("round"),
+                r_.read::<&str, _>("slug").to_owned(),
+            ))
+        })
+        .try_collect::<Vec<_>>()?;
 
-// Database Updating
+    rows.into_iter()
+        .map(|(id, time, winner, dq, completed, round, slug)| {
+            Ok((
+                slug,
+                SetData {
+                    teams: get_set_teams(connection, &id)?,
+                    id: SetId(StringOrInt::from_string(&id)),
+                    time: Some(time),
+                    completed,
+                    round,
+                    winner,
+                    dq,
+                    scores: get_set_scores(connection, &id)?,
+                    // Games do not affect ratings
+                    games: Vec::new(),
+                },
+            ))
+        })
+        .try_collect()
+}
 
-pub fn add_event(connection: &Connection, event: EventId, slug: &str) -> sqlite::Result<()> {
-    let query = "INSERT OR IGNORE INTO events (id, slug) VALUES (?, ?)";
+/// Count the sets of a dataset whose results are not stored locally. This
+/// includes sets that were rated in the dataset but whose event it does not
+/// have stored, as with datasets synced before sets were stored.
+pub fn count_missing_sets(connection: &Connection, dataset: &str) -> sqlite::Result<u64> {
+    let query = format!(
+        r#"SELECT count(*) AS missing FROM (
+        SELECT set_id AS id FROM "{}_set_results"
+        UNION
+        SELECT sets.id FROM dataset_events
+        JOIN sets ON sets.event = dataset_events.event
+        WHERE dataset_events.dataset = :dataset
+    ) WHERE id NOT IN (
+        SELECT sets.id FROM dataset_events
+        JOIN sets ON sets.event = dataset_events.event
+        JOIN set_data ON set_data.id = sets.id
+        WHERE dataset_events.dataset = :dataset
+    )"#,
+        dataset
+    );
 
-    let mut statement = connection.prepare(&query)?;
-    statement.bind((1, event.0 as i64))?;
-    statement.bind((2, slug))?;
-    statement.into_iter().try_for_each(|x| x.map(|_| ()))
+    let mut statement = connection.prepare(query)?;
+    statement.bind((":dataset", dataset))?;
+    statement.next()?;
+    Ok(statement.read::<i64, _>("missing")? as u64)
 }
 
-pub fn add_set(connection: &Connection, set: &SetId, event: EventId) -> sqlite::Result<()> {
-    let query = "INSERT OR IGNORE INTO sets (id, event) VALUES (?, ?)";
+pub fn count_dataset_events(connection: &Connection, dataset: &str) -> sqlite::Result<u64> {
+    let query = "SELECT count(*) AS events FROM dataset_events WHERE dataset = ?";
 
-    let mut statement = connection.prepare(&query)?;
-    statement.bind((1, &set.0.to_string()[..]))?;
-    statement.bind((2, event.0 as i64))?;
-    statement.into_iter().try_for_each(|x| x.map(|_| ()))
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, dataset))?;
+    statement.next()?;
+    Ok(statement.read::<i64, _>("events")? as u64)
 }
 
 pub fn add_players(
     connection: &Connection,
     dataset: &str,
-    players: &Vec<PlayerData>,
+    players: &[PlayerData],
 ) -> sqlite::Result<()> {
     let query1 = "INSERT OR IGNORE INTO players (id, discrim, name, prefix) VALUES (?, ?, ?, ?)";
+    let query1b = "UPDATE OR IGNORE players SET discrim = ? WHERE id = ? AND discrim IS NULL";
     let query2 = format!(
         r#"INSERT OR IGNORE INTO "{}_players" (id) VALUES (?)"#,
         dataset
@@ -290,13 +1461,20 @@ pub fn add_players(
              prefix,
              discrim,
          }| {
-            let mut statement = connection.prepare(&query1)?;
+            let mut statement = connection.prepare(query1)?;
             statement.bind((1, id.0 as i64))?;
-            statement.bind((2, &discrim[..]))?;
+            statement.bind((2, discrim.as_deref()))?;
             statement.bind((3, &name[..]))?;
             statement.bind((4, prefix.as_ref().map(|x| &x[..])))?;
             statement.into_iter().try_for_each(|x| x.map(|_| ()))?;
 
+            if let Some(discrim) = discrim {
+                statement = connection.prepare(query1b)?;
+                statement.bind((1, &discrim[..]))?;
+                statement.bind((2, id.0 as i64))?;
+                statement.into_iter().try_for_each(|x| x.map(|_| ()))?;
+            }
+
             statement = connection.prepare(&query2)?;
             statement.bind((1, id.0 as i64))?;
             statement.into_iter().try_for_each(|x| x.map(|_| ()))
@@ -320,14 +1498,14 @@ pub fn get_all_players(connection: &Connection, dataset: &str) -> sqlite::Result
 pub fn get_player(connection: &Connection, player: PlayerId) -> sqlite::Result<PlayerData> {
     let query = "SELECT name, prefix, discrim FROM players WHERE id = ?";
 
-    let mut statement = connection.prepare(&query)?;
+    let mut statement = connection.prepare(query)?;
     statement.bind((1, player.0 as i64))?;
     statement.next()?;
     Ok(PlayerData {
         id: player,
         name: statement.read::<String, _>("name")?,
         prefix: statement.read::<Option<String>, _>("prefix")?,
-        discrim: statement.read::<String, _>("discrim")?,
+        discrim: statement.read::<Option<String>, _>("discrim")?,
     })
 }
 
@@ -337,22 +1515,188 @@ pub fn get_player_from_discrim(
 ) -> sqlite::Result<PlayerData> {
     let query = "SELECT id, name, prefix FROM players WHERE discrim = ?";
 
-    let mut statement = connection.prepare(&query)?;
+    let mut statement = connection.prepare(query)?;
     statement.bind((1, discrim))?;
     statement.next()?;
     Ok(PlayerData {
         id: PlayerId(statement.read::<i64, _>("id")? as u64),
         name: statement.read::<String, _>("name")?,
         prefix: statement.read::<Option<String>, _>("prefix")?,
-        discrim: discrim.to_owned(),
+        discrim: Some(discrim.to_owned()),
     })
 }
 
+/// Attach a start.gg account to a player that had none.
+pub fn set_player_discrim(
+    connection: &Connection,
+    player: PlayerId,
+    discrim: &str,
+) -> sqlite::Result<()> {
+    let query = "UPDATE players SET discrim = ? WHERE id = ?";
+
+    let mut statement = connection.prepare(query)?;
+    statement.bind((1, discrim))?;
+    statement.bind((2, player.0 as i64))?;
+    statement.into_iter().try_for_each(|x| x.map(|_| ()))
+}
+
+/// Move the set history of one player to another, and remove the first.
+///
+/// The ratings of every dataset containing either player are out of date
+/// afterwards, and must be recomputed from the stored sets.
+pub fn merge_players(
+    connection: &Connection,
+    from: PlayerId,
+    into: PlayerId,
+) -> sqlite::Result<()> {
+    let query = format!(
+        r#"UPDATE OR IGNORE set_players SET player = {1} WHERE player = {0};
+UPDATE OR IGNORE game_selections SET player = {1} WHERE player = {0};
+DELETE FROM set_players WHERE player = {0};
+DELETE FROM game_selections WHERE player = {0};
+DELETE FROM players WHERE id = {0};"#,
+        from.0, into.0
+    );
+
+    connection.execute(query)
+}
+
+pub fn player_in_dataset(
+    connection: &Connection,
+    dataset: &str,
+    player: PlayerId,
+) -> sqlite::Result<bool> {
+    let query = format!(
+        r#"SELECT EXISTS(SELECT 1 FROM "{}_players" WHERE id = ?) AS present"#,
+        dataset
+    );
+
+    let mut statement = connection.prepare(&query)?;
+    statement.bind((1, player.0 as i64))?;
+    statement.next()?;
+    Ok(statement.read::<i64, _>("present")? != 0)
+}
+
+/// The time a set is rated at: when it was completed if start.gg reports it,
+/// otherwise when it started.
+pub fn set_time(set: &SetData, event_time: Timestamp) -> Timestamp {
+    set.completed.or(set.time).unwrap_or(event_time)
+}
+
+/// The key that sets are rated in order of. Sets are ordered by time, then by
+/// bracket round (with each losers round after the winners round of the same
+/// number), then by ID, so that the same sets are always rated in the same
+/// order regardless of which event or sync they came from.
+pub fn set_order(set: &SetData, event_time: Timestamp) -> (Timestamp, u64, bool, SetId) {
+    let round = set.round.unwrap_or(0);
+    (
+        set_time(set, event_time),
+        round.unsigned_abs(),
+        round < 0,
+        set.id.clone(),
+    )
+}
+
+/// A set played by a player, along with where and when it was played.
+pub struct PlayerSetRecord {
+    pub set: SetData,
+    // The team the player was on
+    pub team: usize,
+    pub event: String,
+    // Unknown for events synced by older versions
+    pub tournament: Option<TournamentInfo>,
+}
+
+/// Return the most recent sets a player has played in a dataset, newest first.
+pub fn get_player_history(
+    connection: &Connection,
+    dataset: &str,
+    player: PlayerId,
+    limit: u64,
+) -> sqlite::Result<Vec<PlayerSetRecord>> {
+    let query = "SELECT set_data.id, set_data.time, set_data.winner, set_data.dq,
+        set_data.completed, set_data.round, set_players.team,
+        events.slug, events.name AS event_name,
+        tournaments.id AS tournament_id, tournaments.name AS tournament_name,
+        tournaments.slug AS tournament_slug, tournaments.start AS tournament_start,
+        tournaments.city, tournaments.state, tournaments.country, tournaments.online,
+        tournaments.entrants
+    FROM set_players
+    JOIN sets ON sets.id = set_players.set_id
+    JOIN dataset_events ON dataset_events.event = sets.event
+    JOIN set_data ON set_data.id = sets.id
+    JOIN events ON events.id = sets.event
+    LEFT JOIN tournaments ON tournaments.id = events.tournament
+    WHERE dataset_events.dataset = :dataset AND set_players.player = :player";
+
+    let mut rows = connection
+        .prepare(query)?
+        .into_iter()
+        .bind((":dataset", dataset))?
+        .bind((":player", player.0 as i64))?
+        .map(|r| -> sqlite::Result<_> {
+            let r_ = r?;
+            let tournament = r_
+                .read::<Option<i64>, _>("tournament_id")
+                .map(|id| TournamentInfo {
+                    id: TournamentId(id as u64),
+                    name: r_.read::<&str, _>("tournament_name").to_owned(),
+                    slug: r_.read::<&str, _>("tournament_slug").to_owned(),
+                    time: Timestamp(r_.read::<i64, _>("tournament_start") as u64),
+                    city: r_.read::<Option<&str>, _>("city").map(|x| x.to_owned()),
+                    state: r_.read::<Option<&str>, _>("state").map(|x| x.to_owned()),
+                    country: r_.read::<Option<&str>, _>("country").map(|x| x.to_owned()),
+                    online: r_.read::<i64, _>("online") != 0,
+                    entrants: r_.read::<i64, _>("entrants") as u64,
+                });
+            Ok((
+                r_.read::<&str, _>("id").to_owned(),
+                SetData {
+                    id: SetId(StringOrInt::from_string(r_.read::<&str, _>("id"))),
+                    time: Some(Timestamp(r_.read::<i64, _>("time") as u64)),
+                    completed: r_
+                        .read::<Option<i64>, _>("completed")
+                        .map(|t| Timestamp(t as u64)),
+                    round: r_.read::<Option<i64>, _>("round"),
+                    teams: Vec::new(),
+                    winner: r_.read::<i64, _>("winner") as usize,
+                    dq: r_.read::<i64, _>("dq") != 0,
+                    scores: None,
+                    games: Vec::new(),
+                },
+                r_.read::<i64, _>("team") as usize,
+                r_.read::<Option<&str>, _>("event_name")
+                    .unwrap_or(r_.read::<&str, _>("slug"))
+                    .to_owned(),
+                tournament,
+            ))
+        })
+        .try_collect::<Vec<_>>()?;
+
+    // Sort with the same key that sets are rated in, so that history lists
+    // them in rating order
+    rows.sort_by_cached_key(|(_, set, ..)| std::cmp::Reverse(set_order(set, set.time.unwrap())));
+
+    rows.into_iter()
+        .take(limit as usize)
+        .map(|(id, mut set, team, event, tournament)| {
+            set.teams = get_set_teams(connection, &id)?;
+            set.scores = get_set_scores(connection, &id)?;
+            Ok(PlayerSetRecord {
+                set,
+                team,
+                event,
+                tournament,
+            })
+        })
+        .try_collect()
+}
+
 pub fn match_player_name(connection: &Connection, name: &str) -> sqlite::Result<Vec<PlayerData>> {
     let query = "SELECT * FROM players WHERE name LIKE ?";
 
     connection
-        .prepare(&query)?
+        .prepare(query)?
         .into_iter()
         .bind((1, &format!("%{}%", name)[..]))?
         .map(|r| {
@@ -361,7 +1705,7 @@ pub fn match_player_name(connection: &Connection, name: &str) -> sqlite::Result<
                 id: PlayerId(r_.read::<i64, _>("id") as u64),
                 name: r_.read::<&str, _>("name").to_owned(),
                 prefix: r_.read::<Option<&str>, _>("prefix").map(|x| x.to_owned()),
-                discrim: r_.read::<&str, _>("discrim").to_owned(),
+                discrim: r_.read::<Option<&str>, _>("discrim").map(|x| x.to_owned()),
             })
         })
         .try_collect()
@@ -373,16 +1717,18 @@ pub fn get_player_set_counts(
     player: PlayerId,
 ) -> sqlite::Result<(u64, u64)> {
     let query = format!(
-        r#"SELECT sets_count_won, sets_count_lost FROM "{}_players" WHERE id = ?"#,
+        r#"SELECT
+    (SELECT count(DISTINCT set_id) FROM "{0}_set_results" WHERE winner = :p) AS won,
+    (SELECT count(DISTINCT set_id) FROM "{0}_set_results" WHERE loser = :p) AS lost"#,
         dataset
     );
 
     let mut statement = connection.prepare(&query)?;
-    statement.bind((1, player.0 as i64))?;
+    statement.bind((":p", player.0 as i64))?;
     statement.next()?;
     Ok((
-        statement.read::<i64, _>("sets_count_won")? as u64,
-        statement.read::<i64, _>("sets_count_lost")? as u64,
+        statement.read::<i64, _>("won")? as u64,
+        statement.read::<i64, _>("lost")? as u64,
     ))
 }
 
@@ -393,8 +1739,9 @@ pub fn get_matchup_set_counts(
     player2: PlayerId,
 ) -> sqlite::Result<(u64, u64)> {
     let query = format!(
-        r#"SELECT iif(:a > :b, sets_count_B, sets_count_A) sets_count_A, iif(:a > :b, sets_count_A, sets_count_B) sets_count_B
-            FROM "{}_network" WHERE player_A = min(:a, :b) AND player_B = max(:a, :b)"#,
+        r#"SELECT
+    (SELECT count(*) FROM "{0}_set_results" WHERE winner = :a AND loser = :b) AS won,
+    (SELECT count(*) FROM "{0}_set_results" WHERE winner = :b AND loser = :a) AS lost"#,
         dataset
     );
 
@@ -403,31 +1750,113 @@ pub fn get_matchup_set_counts(
     statement.bind((":b", player2.0 as i64))?;
     statement.next()?;
     Ok((
-        statement.read::<i64, _>("sets_count_A")? as u64,
-        statement.read::<i64, _>("sets_count_B")? as u64,
+        statement.read::<i64, _>("won")? as u64,
+        statement.read::<i64, _>("lost")? as u64,
     ))
 }
 
-pub fn set_player_set_counts(
+/// Return the characters a player has played in a dataset, along with the
+/// number of games played as each, most played first.
+pub fn get_character_usage(
     connection: &Connection,
     dataset: &str,
     player: PlayerId,
-    won: bool,
+) -> sqlite::Result<Vec<(String, u64)>> {
+    let query = "SELECT characters.name, count(*) AS games FROM game_selections
+    JOIN characters ON characters.id = game_selections.character
+    JOIN sets ON sets.id = game_selections.set_id
+    JOIN dataset_events ON dataset_events.event = sets.event
+    WHERE dataset_events.dataset = ? AND game_selections.player = ?
+    GROUP BY game_selections.character
+    ORDER BY games DESC, characters.name";
+
+    connection
+        .prepare(query)?
+        .into_iter()
+        .bind((1, dataset))?
+        .bind((2, player.0 as i64))?
+        .map(|r| {
+            let r_ = r?;
+            Ok((
+                r_.read::<&str, _>("name").to_owned(),
+                r_.read::<i64, _>("games") as u64,
+            ))
+        })
+        .try_collect()
+}
+
+/// Return the games won and lost by the first player against the second in a
+/// dataset, grouped by the characters each of them played.
+pub fn get_character_matchups(
+    connection: &Connection,
+    dataset: &str,
+    player1: PlayerId,
+    player2: PlayerId,
+) -> sqlite::Result<Vec<(String, String, u64, u64)>> {
+    let query = "SELECT c1.name AS name1, c2.name AS name2,
+        coalesce(sum(set_games.winner = p1.team), 0) AS won,
+        coalesce(sum(set_games.winner = p2.team), 0) AS lost
+    FROM set_games
+    JOIN sets ON sets.id = set_games.set_id
+    JOIN dataset_events ON dataset_events.event = sets.event
+    JOIN set_players p1 ON p1.set_id = set_games.set_id AND p1.player = :a
+    JOIN set_players p2 ON p2.set_id = set_games.set_id AND p2.player = :b
+    JOIN game_selections s1 ON s1.set_id = set_games.set_id
+        AND s1.game = set_games.game AND s1.player = :a
+    JOIN game_selections s2 ON s2.set_id = set_games.set_id
+        AND s2.game = set_games.game AND s2.player = :b
+    JOIN characters c1 ON c1.id = s1.character
+    JOIN characters c2 ON c2.id = s2.character
+    WHERE dataset_events.dataset = :dataset AND p1.team != p2.team
+    GROUP BY s1.character, s2.character
+    ORDER BY count(*) DESC, name1, name2";
+
+    connection
+        .prepare(query)?
+        .into_iter()
+        .bind((":a", player1.0 as i64))?
+        .bind((":b", player2.0 as i64))?
+        .bind((":dataset", dataset))?
+        .map(|r| {
+            let r_ = r?;
+            Ok((
+                r_.read::<&str, _>("name1").to_owned(),
+                r_.read::<&str, _>("name2").to_owned(),
+                r_.read::<i64, _>("won") as u64,
+                r_.read::<i64, _>("lost") as u64,
+            ))
+        })
+        .try_collect()
+}
+
+/// Record the result of a rated set between two opposing players.
+pub fn add_set_result(
+    connection: &Connection,
+    dataset: &str,
     set: &SetId,
+    time: Timestamp,
+    (winner, loser): (PlayerId, PlayerId),
+    scores: Option<(u64, u64)>,
+    delta: f64,
 ) -> sqlite::Result<()> {
     let query = format!(
-        r#"UPDATE "{}_players" SET
-sets_won = iif(:won, sets_won || :set || ';', sets_won),
-sets_lost = iif(:won, sets_lost, sets_lost || :set || ';') WHERE id = :id"#,
+        r#"INSERT OR REPLACE INTO "{}_set_results"
+    (set_id, event, time, winner, loser, winner_score, loser_score, delta)
+    VALUES (:set, (SELECT event FROM sets WHERE id = :set), :t, :w, :l, :ws, :ls, :d)"#,
         dataset
     );
 
-    let mut statement = connection.prepare(&query)?;
-    statement.bind((":id", player.0 as i64))?;
-    statement.bind((":won", if won { 1 } else { 0 }))?;
-    statement.bind((":set", &set.0.to_string()[..]))?;
-    statement.next()?;
-    Ok(())
+    connection
+        .prepare(query)?
+        .into_iter()
+        .bind((":set", &set.0.to_string()[..]))?
+        .bind((":t", time.0 as i64))?
+        .bind((":w", winner.0 as i64))?
+        .bind((":l", loser.0 as i64))?
+        .bind((":ws", scores.map(|(score, _)| score as i64)))?
+        .bind((":ls", scores.map(|(_, score)| score as i64)))?
+        .bind((":d", delta))?
+        .try_for_each(|x| x.map(|_| ()))
 }
 
 pub fn get_network_data(
@@ -502,23 +1931,27 @@ WHERE player_A = :i OR player_B = :i"#,
     statement.into_iter().try_for_each(|x| x.map(|_| ()))
 }
 
+/// Update the network from the result of a set between two players, and
+/// return the change in the second player's advantage over the first.
+#[allow(clippy::too_many_arguments)]
 pub fn glicko_adjust(
     connection: &Connection,
     dataset: &str,
-    set: &SetId,
     player1: PlayerId,
     player2: PlayerId,
     advantage: f64,
     variance: f64,
     winner: usize,
     decay_rate: f64,
-) -> sqlite::Result<()> {
+    weight: f64,
+) -> sqlite::Result<f64> {
     let score = if winner != 0 { 1.0 } else { 0.0 };
 
     let exp_val = 1.0 / (1.0 + (-advantage).exp());
 
+    // A weight below 1 makes the set count as less than one full observation
     let like_var = 1.0 / exp_val / (1.0 - exp_val);
-    let var_new = 1.0 / (1.0 / variance + 1.0 / like_var);
+    let var_new = 1.0 / (1.0 / variance + weight / like_var);
     let adjust = score - exp_val;
 
     let query1 = format!(
@@ -533,9 +1966,7 @@ WHERE (player_A = :pl AND player_B != :plo)
     let query2 = format!(
         r#"UPDATE "{}_network" SET
 variance = :var,
-advantage = advantage + iif(:a > :b, -:adj, :adj) * :var,
-sets_A = iif(:w = (:a > :b), sets_A || :set || ';', sets_A),
-sets_B = iif(:w = (:b > :a), sets_B || :set || ';', sets_B)
+advantage = advantage + iif(:a > :b, -:adj, :adj) * :var
 WHERE player_A = min(:a, :b) AND player_B = max(:a, :b)"#,
         dataset
     );
@@ -544,7 +1975,7 @@ WHERE player_A = min(:a, :b) AND player_B = max(:a, :b)"#,
     statement.bind((":pl", player1.0 as i64))?;
     statement.bind((":plo", player2.0 as i64))?;
     statement.bind((":adj", -0.5 * adjust))?;
-    statement.bind((":d", decay_rate))?;
+    statement.bind((":d", decay_rate * weight))?;
     statement.bind((":lv", like_var))?;
     statement.into_iter().try_for_each(|x| x.map(|_| ()))?;
 
@@ -552,18 +1983,18 @@ WHERE player_A = min(:a, :b) AND player_B = max(:a, :b)"#,
     statement.bind((":pl", player2.0 as i64))?;
     statement.bind((":plo", player1.0 as i64))?;
     statement.bind((":adj", 0.5 * adjust))?;
-    statement.bind((":d", decay_rate))?;
+    statement.bind((":d", decay_rate * weight))?;
     statement.bind((":lv", like_var))?;
     statement.into_iter().try_for_each(|x| x.map(|_| ()))?;
 
     statement = connection.prepare(&query2)?;
     statement.bind((":a", player1.0 as i64))?;
     statement.bind((":b", player2.0 as i64))?;
-    statement.bind((":adj", adjust))?;
+    statement.bind((":adj", weight * adjust))?;
     statement.bind((":var", var_new))?;
-    statement.bind((":w", winner as i64))?;
-    statement.bind((":set", &set.0.to_string()[..]))?;
-    statement.into_iter().try_for_each(|x| x.map(|_| ()))
+    statement.into_iter().try_for_each(|x| x.map(|_| ()))?;
+
+    Ok(weight * adjust * var_new)
 }
 
 pub fn get_edges(
@@ -635,8 +2066,11 @@ pub fn hypothetical_advantage(
         return Ok((0.0, 5.0));
     }
 
+    // Paths to a player, as (advantage, variance, decay) triples
+    type Paths = Vec<(f64, f64, f64)>;
+
     let mut visited: HashSet<PlayerId> = HashSet::new();
-    let mut queue: VecDeque<(PlayerId, Vec<(f64, f64, f64)>)> =
+    let mut queue: VecDeque<(PlayerId, Paths)> =
         VecDeque::from([(player1, Vec::from([(0.0, 0.0, 1.0 / decay_rate)]))]);
 
     let mut final_paths = Vec::new();
@@ -672,7 +2106,7 @@ pub fn hypothetical_advantage(
         visited.insert(visiting);
     }
 
-    if final_paths.len() == 0 {
+    if final_paths.is_empty() {
         // No paths found
         Ok((0.0, 5.0))
     } else {
@@ -685,7 +2119,7 @@ pub fn hypothetical_advantage(
         let mut final_adv = final_adv / sum_decay;
         let mut final_var = final_var / sum_decay - final_adv * final_adv;
         if final_var > 5.0 {
-            final_adv = final_adv * (5.0 / final_var).sqrt();
+            final_adv *= (5.0 / final_var).sqrt();
             final_var = 5.0;
         }
         Ok((final_adv, final_var))
@@ -713,43 +2147,9 @@ pub mod tests {
 
     // Mock a database file in transient memory
     pub fn mock_datasets() -> sqlite::Result<Connection> {
-        let query = "PRAGMA foreign_keys = ON;
-
-CREATE TABLE IF NOT EXISTS datasets (
-    name TEXT UNIQUE NOT NULL,
-    last_sync INTEGER NOT NULL,
-    game_id INTEGER NOT NULL,
-    game_name TEXT NOT NULL,
-    game_slug TEXT NOT NULL,
-    country TEXT,
-    state TEXT,
-    set_limit INTEGER NOT NULL,
-    decay_rate REAL NOT NULL,
-    adj_decay_rate REAL NOT NULL,
-    var_const
-) STRICT;
-
-CREATE TABLE IF NOT EXISTS players (
-    id INTEGER PRIMARY KEY,
-    discrim TEXT UNIQUE NOT NULL,
-    name TEXT NOT NULL,
-    prefix TEXT
-) STRICT;
-
-CREATE TABLE IF NOT EXISTS events (
-    id INTEGER PRIMARY KEY,
-    slug TEXT NOT NULL
-) STRICT;
-
-CREATE TABLE IF NOT EXISTS sets (
-    id TEXT UNIQUE NOT NULL,
-    event INTEGER NOT NULL,
-    FOREIGN KEY(event) REFERENCES events
-) STRICT;
-";
-
         let connection = sqlite::open(":memory:")?;
-        connection.execute(query)?;
+        connection.execute("PRAGMA foreign_keys = ON;")?;
+        create_schema(&connection)?;
         Ok(connection)
     }
 
@@ -760,13 +2160,18 @@ CREATE TABLE IF NOT EXISTS sets (
             start: Timestamp(1),
             end: None,
             last_sync: Timestamp(1),
-            game_id: VideogameId(0),
-            game_name: String::from("Test Game"),
-            game_slug: String::from("test"),
-            country: None,
-            state: None,
+            games: vec![VideogameData {
+                id: VideogameId(0),
+                name: String::from("Test Game"),
+                slug: String::from("test"),
+            }],
+            locations: Vec::new(),
+            online: OnlinePolicy::Both,
+            rules: EventRules::default(),
             decay_const: 0.5,
             var_const: 0.00000001,
+            rate_teams: false,
+            weight_margin: false,
         }
     }
 
@@ -776,7 +2181,7 @@ CREATE TABLE IF NOT EXISTS sets (
                 id: PlayerId(i),
                 name: format!("{}", i),
                 prefix: None,
-                discrim: String::from("a"),
+                discrim: None,
             })
             .collect()
     }
@@ -4,12 +4,17 @@
 use chrono::{Local, TimeZone, Utc};
 use clap::{Parser, Subcommand};
 use sqlite::*;
-use std::{cmp::min, path::PathBuf};
+use std::cmp::min;
+use std::path::{Path, PathBuf};
 
 mod queries;
 use queries::*;
+mod cache;
+use cache::*;
 mod database;
 use database::*;
+mod progress;
+use progress::*;
 mod sync;
 use sync::*;
 mod util;
@@ -48,6 +53,39 @@ AUTH_TOKEN, or in a text file '<CONFIG_DIR>/auth.txt'."
         long_help = "This flag overrides the default config directory."
     )]
     config_dir: Option<PathBuf>,
+
+    #[arg(
+        long = "endpoint",
+        value_name = "URL",
+        global = true,
+        help = "start.gg API endpoint",
+        long_help = "The URL of the start.gg GraphQL API.
+An endpoint can be specified using this argument, in the environment variable
+STARTGG_ENDPOINT, or in a text file '<CONFIG_DIR>/endpoint.txt'. If none is
+given, the official start.gg API is used."
+    )]
+    endpoint: Option<String>,
+
+    #[arg(
+        long = "no-cache",
+        global = true,
+        help = "Do not use the response cache",
+        long_help = "Always request data from start.gg, without reading from or
+writing to the local cache of start.gg responses."
+    )]
+    no_cache: bool,
+
+    #[arg(
+        long = "log-format",
+        value_name = "FORMAT",
+        global = true,
+        default_value = "text",
+        help = "Format of sync progress output",
+        long_help = "The format that sync progress is reported in. With 'json', one
+JSON record is written to stdout for each event synced and one for each dataset,
+and all other messages are written to stderr."
+    )]
+    log_format: LogFormat,
 }
 
 #[derive(Subcommand)]
@@ -73,6 +111,25 @@ created if it does not already exist."
         datasets: Vec<String>,
         #[arg(short, long, help = "Sync all stored databases")]
         all: bool,
+        #[arg(
+            long,
+            help = "Show what would be synced without syncing",
+            long_help = "List the events that would be synced and whether the
+dataset's rules would skip them, along with an estimate of the number of requests
+needed, without fetching any sets or changing the dataset."
+        )]
+        dry_run: bool,
+        #[arg(
+            long,
+            value_name = "DAYS",
+            default_value_t = 0,
+            help = "Re-check events from this many days before the last sync",
+            long_help = "Fetch the sets of events synced in this many days before
+the last sync again, to pick up sets reported late and results that were corrected.
+New sets are rated immediately; corrected results are stored, and the dataset can
+then be recomputed to update ratings from them."
+        )]
+        lookback: u64,
     },
     #[command(about = "Access player information")]
     Player {
@@ -87,6 +144,16 @@ created if it does not already exist."
         #[arg(short, long, global = true, help = "The dataset to access")]
         dataset: Option<String>,
     },
+    #[command(
+        about = "Manage the response cache",
+        long_about = "Inspect or purge the local cache of start.gg responses.
+Data that can never change, such as the sets of a completed event, is stored in
+this cache so that it does not have to be downloaded again."
+    )]
+    Cache {
+        #[command(subcommand)]
+        subcommand: CacheSC,
+    },
 }
 
 #[derive(Subcommand)]
@@ -102,14 +169,120 @@ enum DatasetSC {
         old: Option<String>,
         new: Option<String>,
     },
+    #[command(
+        about = "Recompute a dataset from stored sets",
+        long_about = "Rebuild the ratings of a dataset by replaying every set it
+has synced, without accessing start.gg. New values for the dataset's parameters
+can be given to change them."
+    )]
+    Recompute {
+        name: Option<String>,
+        #[arg(long, value_name = "DECAY", help = "New network decay constant")]
+        decay_const: Option<f64>,
+        #[arg(long, value_name = "RATE", help = "New variance rate")]
+        var_const: Option<f64>,
+        #[arg(
+            long,
+            value_name = "BOOL",
+            help = "Whether to weight sets by their game margin"
+        )]
+        weight_margin: Option<bool>,
+    },
+    #[command(
+        about = "Change which events a dataset includes",
+        long_about = "Change the rules a dataset uses to decide which events to
+include. The new rules apply to events synced from now on."
+    )]
+    Rules { name: Option<String> },
+    #[command(
+        about = "Exclude a tournament or event from a dataset",
+        long_about = "Exclude a tournament or event from a dataset, given its
+start.gg URL or slug. Excluded events are skipped when syncing; to remove the
+sets of an event that has already been synced, recompute the dataset."
+    )]
+    Exclude {
+        name: String,
+        event: String,
+        #[arg(long, help = "Remove the exclusion instead of adding it")]
+        remove: bool,
+        #[arg(long, help = "Recompute the dataset afterwards")]
+        recompute: bool,
+    },
+    #[command(
+        about = "Always include a tournament or event in a dataset",
+        long_about = "Include a tournament or event in a dataset even if the
+dataset's event rules would skip it, given its start.gg URL or slug."
+    )]
+    Include {
+        name: String,
+        event: String,
+        #[arg(long, help = "Remove the inclusion instead of adding it")]
+        remove: bool,
+    },
+    #[command(
+        about = "Add a single event to a dataset",
+        long_about = "Add the sets of a single event to a dataset, given its
+start.gg URL or slug. This can be used for events that syncing does not find,
+such as events listed under the wrong location or game."
+    )]
+    ImportEvent { name: String, event: String },
 }
 
 #[derive(Subcommand)]
 enum PlayerSC {
     #[command(about = "Get info about a player")]
     Info { player: String },
-    #[command(about = "Matchup data between two players")]
-    Matchup { player1: String, player2: String },
+    #[command(about = "List a player's most recent sets")]
+    History {
+        player: String,
+        #[arg(
+            short = 'n',
+            long,
+            value_name = "NUM",
+            default_value_t = 20,
+            help = "Number of sets to show"
+        )]
+        limit: u64,
+    },
+    #[command(about = "Matchup data between two players or teams")]
+    Matchup {
+        player1: String,
+        player2: String,
+        #[arg(long, value_name = "PLAYER", help = "Teammate of the first player")]
+        partner1: Option<String>,
+        #[arg(long, value_name = "PLAYER", help = "Teammate of the second player")]
+        partner2: Option<String>,
+        #[arg(
+            long,
+            help = "Break down games by character",
+            long_help = "Show the games won and lost in this matchup for each
+pairing of characters played. Only available for singles matchups."
+        )]
+        characters: bool,
+    },
+    #[command(
+        about = "Link a player to a start.gg account",
+        long_about = "Link a player who had no start.gg account when their sets
+were synced to the account they use now. If the account already has its own
+player, the two are merged into one, and every dataset containing them is
+recomputed so that no sets are lost."
+    )]
+    Link {
+        player: String,
+        #[arg(help = "The account's user discriminator")]
+        account: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheSC {
+    #[command(about = "Show the size of the cache")]
+    Info,
+    #[command(about = "Delete cached responses")]
+    Purge {
+        #[arg(help = "Only delete responses to this query (e.g. EventSets)")]
+        query: Option<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -130,8 +303,16 @@ fn main() {
     let mut data_dir = dirs::data_dir().expect("Could not determine user data directory");
     data_dir.push("startrnr");
 
-    let connection =
-        open_datasets(&data_dir).unwrap_or_else(|_| error("Could not open datasets file", 2));
+    set_endpoint(get_endpoint(cli.endpoint, &config_dir));
+    set_log_format(cli.log_format);
+
+    let cache_dir = data_dir.join("cache");
+    if !cli.no_cache {
+        enable_cache(cache_dir.clone());
+    }
+
+    let connection = open_datasets(&data_dir)
+        .unwrap_or_else(|e| error(&format!("Could not open datasets file ({})", e), 2));
 
     #[allow(unreachable_patterns)]
     match cli.subcommand {
@@ -147,25 +328,116 @@ fn main() {
         Subcommands::Dataset {
             subcommand: DatasetSC::Rename { old, new },
         } => dataset_rename(&connection, old, new),
+        Subcommands::Dataset {
+            subcommand:
+                DatasetSC::Recompute {
+                    name,
+                    decay_const,
+                    var_const,
+                    weight_margin,
+                },
+        } => dataset_recompute(&connection, name, decay_const, var_const, weight_margin),
+        Subcommands::Dataset {
+            subcommand: DatasetSC::Rules { name },
+        } => dataset_rules(&connection, name),
+        Subcommands::Dataset {
+            subcommand:
+                DatasetSC::Exclude {
+                    name,
+                    event,
+                    remove,
+                    recompute,
+                },
+        } => {
+            dataset_filter(&connection, &name, &event, false, remove);
+            if recompute {
+                dataset_recompute(&connection, Some(name), None, None, None);
+            }
+        }
+        Subcommands::Dataset {
+            subcommand:
+                DatasetSC::Include {
+                    name,
+                    event,
+                    remove,
+                },
+        } => dataset_filter(&connection, &name, &event, true, remove),
+        Subcommands::Dataset {
+            subcommand: DatasetSC::ImportEvent { name, event },
+        } => dataset_import_event(&connection, get_auth_token(&config_dir), &name, &event),
 
         Subcommands::Player {
             subcommand: PlayerSC::Info { player },
             dataset,
         } => player_info(&connection, dataset, player),
         Subcommands::Player {
-            subcommand: PlayerSC::Matchup { player1, player2 },
+            subcommand: PlayerSC::History { player, limit },
             dataset,
-        } => player_matchup(&connection, dataset, player1, player2),
-
-        Subcommands::Sync { datasets, all } => {
-            sync(&connection, get_auth_token(&config_dir), datasets, all)
+        } => player_history(&connection, dataset, player, limit),
+        Subcommands::Player {
+            subcommand: PlayerSC::Link { player, account },
+            dataset: _,
+        } => player_link(&connection, player, account),
+        Subcommands::Player {
+            subcommand:
+                PlayerSC::Matchup {
+                    player1,
+                    player2,
+                    partner1: None,
+                    partner2: None,
+                    characters,
+                },
+            dataset,
+        } => player_matchup(&connection, dataset, player1, player2, characters),
+        Subcommands::Player {
+            subcommand:
+                PlayerSC::Matchup {
+                    player1,
+                    player2,
+                    partner1,
+                    partner2,
+                    characters,
+                },
+            dataset,
+        } => {
+            // Character stats are only kept for singles sets
+            if characters {
+                error("--characters can only be used for singles matchups", 1);
+            }
+            team_matchup(
+                &connection,
+                dataset,
+                [Some(player1), partner1].into_iter().flatten().collect(),
+                [Some(player2), partner2].into_iter().flatten().collect(),
+            )
         }
 
+        Subcommands::Sync {
+            datasets,
+            all,
+            dry_run,
+            lookback,
+        } => sync(
+            &connection,
+            get_auth_token(&config_dir),
+            datasets,
+            all,
+            dry_run,
+            lookback,
+        ),
+
         Subcommands::Ranking {
             subcommand: RankingSC::Create,
             dataset,
         } => ranking_create(&connection, dataset),
 
+        Subcommands::Cache {
+            subcommand: CacheSC::Info,
+        } => cache_info(&cache_dir),
+        Subcommands::Cache {
+            subcommand: CacheSC::Purge { query },
+        } => cache_purge(&cache_dir, query),
+
         _ => eprintln!("This feature is currently unimplemented."),
     }
 }
@@ -173,23 +445,67 @@ fn main() {
 // Datasets
 
 fn dataset_list(connection: &Connection) {
-    let datasets = list_datasets(&connection).expect("Error communicating with SQLite");
+    let datasets = list_datasets(connection).expect("Error communicating with SQLite");
 
     for (name, metadata) in datasets {
-        print!(
-            "· \x1b[1m\x1b[34m{}\x1b[0m
-\x1b[4m\x1b]8;;https://www.start.gg/{}\x1b\\{}\x1b]8;;\x1b\\\x1b[0m ",
-            name, metadata.game_slug, metadata.game_name
-        );
+        println!("· \x1b[1m\x1b[34m{}\x1b[0m", name);
+        for game in metadata.games.iter() {
+            print!(
+                "\x1b[4m\x1b]8;;https://www.start.gg/{}\x1b\\{}\x1b]8;;\x1b\\\x1b[0m ",
+                game.slug, game.name
+            );
+        }
 
-        if let Some(country) = metadata.country {
-            if let Some(state) = metadata.state {
-                println!("(in {}, {})", country, state);
-            } else {
-                println!("(in {})", country);
-            }
-        } else {
+        if metadata.locations.is_empty() {
             println!("(Global)");
+        } else {
+            let locations = metadata
+                .locations
+                .iter()
+                .map(|location| match location {
+                    Location::Region {
+                        country,
+                        state: Some(state),
+                    } => format!("in {}, {}", country, state),
+                    Location::Region {
+                        country,
+                        state: None,
+                    } => format!("in {}", country),
+                    Location::Radius {
+                        latitude,
+                        longitude,
+                        distance,
+                    } => format!("within {} of {}, {}", distance, latitude, longitude),
+                })
+                .collect::<Vec<_>>();
+            println!("({})", locations.join("; "));
+        }
+
+        match metadata.online {
+            OnlinePolicy::Offline => println!("Offline events only"),
+            OnlinePolicy::Online => println!("Online events only"),
+            OnlinePolicy::Both => (),
+        }
+
+        let rules = &metadata.rules;
+        if !rules.blocklist.is_empty() {
+            println!("Excluding events matching: {}", rules.blocklist.join(", "));
+        }
+        if rules.min_entrants > 1 {
+            println!("Events with at least {} entrants", rules.min_entrants);
+        }
+        if rules.singles_only {
+            println!("Singles events only");
+        }
+
+        let filters =
+            get_event_filters(connection, &name).expect("Error communicating with SQLite");
+        for (slug, include) in filters {
+            println!(
+                "{} \x1b[4m\x1b]8;;https://www.start.gg/{1}\x1b\\{1}\x1b]8;;\x1b\\\x1b[0m",
+                if include { "Including" } else { "Excluding" },
+                slug
+            );
         }
 
         let start = if metadata.start.0 != 1 {
@@ -241,6 +557,12 @@ fn dataset_list(connection: &Connection) {
             "\n\x1b[1mNetwork Decay Constant:\x1b[0m {}",
             metadata.decay_const
         );
+        if metadata.rate_teams {
+            println!("\x1b[1mRates team sets\x1b[0m");
+        }
+        if metadata.weight_margin {
+            println!("\x1b[1mWeights sets by game margin\x1b[0m");
+        }
     }
 }
 
@@ -254,42 +576,116 @@ fn dataset_new(connection: &Connection, auth: String, name: Option<String>) {
 
     // Game
 
-    print!("Search games: ");
-    let games = run_query::<VideogameSearch, _>(
-        VideogameSearchVars {
-            name: &read_string(),
-        },
-        &auth,
-    )
-    .unwrap_or_else(|| error("Could not access start.gg", 1));
-
-    if games.is_empty() {
-        issue("No games found!", 0);
-    }
+    let mut games = Vec::<VideogameData>::new();
+    loop {
+        print!("Search games: ");
+        let results = run_query::<VideogameSearch, _>(
+            VideogameSearchVars {
+                name: &read_string(),
+            },
+            &auth,
+        )
+        .unwrap_or_else(|| error("Could not access start.gg", 1));
+
+        if results.is_empty() {
+            if games.is_empty() {
+                issue("No games found!", 0);
+            }
+            println!("No games found");
+        } else {
+            println!("\nSearch results:");
+            for (i, game) in results.iter().enumerate() {
+                println!("{} - {}", i, game.name);
+            }
 
-    println!("\nSearch results:");
-    for (i, game) in games.iter().enumerate() {
-        println!("{} - {}", i, game.name);
-    }
+            print!(
+                "\nGames to track ratings for, separated by commas (0-{}): ",
+                results.len() - 1
+            );
+            for input in read_string().split(',') {
+                let index = input
+                    .trim()
+                    .parse::<usize>()
+                    .unwrap_or_else(|_| error("Not an integer", 1));
+                if index >= results.len() {
+                    error("Out of range!", 1);
+                }
+                if games.iter().all(|game| game.id != results[index].id) {
+                    games.push(results[index].clone());
+                }
+            }
+        }
 
-    print!("\nGame to track ratings for (0-{}): ", games.len() - 1);
-    let index = read_string()
-        .parse::<usize>()
-        .unwrap_or_else(|_| error("Not an integer", 1));
-    if index >= games.len() {
-        error("Out of range!", 1);
+        print!("\nSearch for more games to track? (y/n) ");
+        if !read_string().starts_with('y') {
+            break;
+        }
     }
 
-    let VideogameData {
-        id: game_id,
-        name: game_name,
-        slug: game_slug,
-    } = games[index].clone();
-
     // Location
 
     print!(
         "
+\x1b[1mLocation\x1b[0m
+A dataset can track ratings in one or more regions (countries, or states and
+provinces within them), or in the area within some distance of a point.
+
+Track ratings by region or by distance? (region/distance, default region): "
+    );
+    let by_distance = match read_string().to_lowercase().as_str() {
+        "" | "region" => false,
+        "distance" => true,
+        _ => error("Input is not 'region' or 'distance'", 1),
+    };
+
+    let mut locations = Vec::new();
+    if by_distance {
+        print!(
+            "
+\x1b[1mCenter\x1b[0m
+Enter the latitude and longitude of the center of the area you want to track
+ratings in, e.g. \"41.88,-87.63\" for Chicago.
+
+Center (latitude,longitude): "
+        );
+        let (latitude, longitude) = read_string()
+            .split_once(',')
+            .and_then(|(lat, lng)| {
+                Some((
+                    lat.trim().parse::<f64>().ok()?,
+                    lng.trim().parse::<f64>().ok()?,
+                ))
+            })
+            .filter(|(lat, lng)| lat.abs() <= 90.0 && lng.abs() <= 180.0)
+            .unwrap_or_else(|| error("Input is not a latitude and longitude", 1));
+
+        print!(
+            "
+\x1b[1mDistance\x1b[0m
+Enter the distance from the center to track ratings within, in miles (mi) or
+kilometers (km), e.g. \"150mi\".
+
+Distance: "
+        );
+        let distance = read_string().to_lowercase().replace(' ', "");
+        let valid = ["mi", "km"].iter().any(|unit| {
+            distance
+                .strip_suffix(unit)
+                .is_some_and(|n| n.parse::<f64>().is_ok_and(|n| n > 0.0))
+        });
+        if !valid {
+            error("Input is not a distance in miles or kilometers", 1);
+        }
+
+        locations.push(Location::Radius {
+            latitude,
+            longitude,
+            distance,
+        });
+    } else {
+        loop {
+            print!(
+                "
 \x1b[1mCountry\x1b[0m
 Enter the two-letter code for the country you want to track ratings in, e.g.
 \"US\" for the United States. See \x1b[4m\x1b]8;;https://www.ups.com/worldshiphelp/\
@@ -298,22 +694,22 @@ WSA/ENU/AppHelp/mergedProjects/CORE/Codes/Country_Territory_and_Currency_Codes.h
 If no code is entered, then the dataset will track all players globally.
 
 Country to track ratings for (leave empty for none): "
-    );
-    let country = {
-        let mut string = read_string();
-        if string.is_empty() {
-            None
-        } else if string.len() == 2 && string.chars().all(|c| c.is_ascii_alphabetic()) {
-            string.make_ascii_uppercase();
-            Some(string)
-        } else {
-            error("Input is not a two-letter code", 1);
-        }
-    };
-
-    let state = if country.as_ref().is_some_and(|s| s == "US" || s == "CA") {
-        print!(
-            "
+            );
+            let country = {
+                let mut string = read_string();
+                if string.is_empty() {
+                    break;
+                } else if string.len() == 2 && string.chars().all(|c| c.is_ascii_alphabetic()) {
+                    string.make_ascii_uppercase();
+                    string
+                } else {
+                    error("Input is not a two-letter code", 1);
+                }
+            };
+
+            let state = if country == "US" || country == "CA" {
+                print!(
+                    "
 \x1b[1mState/Province\x1b[0m
 Enter the two-letter code for the US state or Canadian province you want to track
 ratings in, e.g. \"CA\" for California. See \x1b[4m\x1b]8;;https://www.ups.com/worldshiphelp/\
@@ -322,20 +718,56 @@ WSA/ENU/AppHelp/mergedProjects/CORE/Codes/State_Province_Codes.htm\x1b\\this sit
 If no code is entered, then the dataset will track all players within the country.
 
 State/province to track ratings for (leave empty for none): "
-        );
-        let mut string = read_string();
-        if string.is_empty() {
-            None
-        } else if string.len() == 2 && string.chars().all(|c| c.is_ascii_alphabetic()) {
-            string.make_ascii_uppercase();
-            Some(string)
-        } else {
-            error("Input is not a two-letter code", 1);
+                );
+                let mut string = read_string();
+                if string.is_empty() {
+                    None
+                } else if string.len() == 2 && string.chars().all(|c| c.is_ascii_alphabetic()) {
+                    string.make_ascii_uppercase();
+                    Some(string)
+                } else {
+                    error("Input is not a two-letter code", 1);
+                }
+            } else {
+                None
+            };
+
+            locations.push(Location::Region { country, state });
+
+            print!("\nTrack another region as well? (y/n) ");
+            if !read_string().starts_with('y') {
+                break;
+            }
         }
-    } else {
-        None
+    }
+
+    // Online Events
+
+    print!(
+        "
+\x1b[1mOnline Events\x1b[0m
+Results from online events often differ from results at offline events, so a
+dataset can track offline events only, online events only, or both. Note that
+online events usually have no location, and will not be found by a dataset that
+tracks a specific country.
+
+Events to track (offline, online, or both; default both): "
+    );
+    let online = match read_string().to_lowercase().as_str() {
+        "" | "both" => OnlinePolicy::Both,
+        "offline" => OnlinePolicy::Offline,
+        "online" => OnlinePolicy::Online,
+        _ => error("Input is not 'offline', 'online' or 'both'", 1),
     };
 
+    // Event Rules
+
+    let rules = read_event_rules(EventRules {
+        blocklist: DEFAULT_BLOCKLIST.iter().map(|s| s.to_string()).collect(),
+        min_entrants: 0,
+        singles_only: false,
+    });
+
     // Interval
 
     print!(
@@ -405,6 +837,8 @@ End date (year, m/y, or m/d/y): "
     // Defaults
     let mut decay_const = 0.9;
     let mut var_const = (10.0 - 0.04) / SECS_IN_YEAR as f64 / 3.0;
+    let mut rate_teams = false;
+    let mut weight_margin = false;
 
     print!("\nConfigure advanced options? (y/n) ");
     if let Some('y') = read_string().chars().next() {
@@ -427,7 +861,7 @@ Network decay constant (default 0.9): "
             decay_const = decay_const_input
                 .parse::<f64>()
                 .unwrap_or_else(|_| error("Input is not a number", 1));
-            if decay_const < 0.0 || decay_const > 1.0 {
+            if !(0.0..=1.0).contains(&decay_const) {
                 error("Input is not between 0 and 1", 1);
             }
         }
@@ -451,6 +885,32 @@ Variance rate: "
                 .parse::<f64>()
                 .unwrap_or_else(|_| error("Input is not a number", 1));
         }
+
+        // Team Sets
+
+        print!(
+            "
+\x1b[1mTeam Sets\x1b[0m
+By default, only singles sets are used to rate players. If team sets (such as
+doubles) are rated, each player is rated against every player on the opposing
+team, with each pairing counting as a fraction of a set.
+
+Rate team sets? (y/n) "
+        );
+        rate_teams = read_string().starts_with('y');
+
+        // Game Margin
+
+        print!(
+            "
+\x1b[1mGame Margin\x1b[0m
+By default, every set counts the same no matter its score. If sets are weighted
+by game margin, a close set (such as 3-2) counts for a little over half as much
+as a sweep (such as 3-0). Sets without reported game counts count fully.
+
+Weight sets by game margin? (y/n) "
+        );
+        weight_margin = read_string().starts_with('y');
     }
 
     // Done configuring
@@ -462,13 +922,14 @@ Variance rate: "
             start,
             end,
             last_sync: start,
-            game_id,
-            game_name,
-            game_slug,
-            country,
-            state,
+            games,
+            locations,
+            online,
+            rules,
             decay_const,
             var_const,
+            rate_teams,
+            weight_margin,
         },
     )
     .expect("Error communicating with SQLite");
@@ -476,6 +937,88 @@ Variance rate: "
     println!("\nCreated dataset {}", name);
 }
 
+const DEFAULT_BLOCKLIST: [&str; 4] = ["amateur", "redemption", "ladder", "crew"];
+
+fn read_event_rules(default: EventRules) -> EventRules {
+    print!(
+        "
+\x1b[1mExcluded Events\x1b[0m
+Side events such as amateur brackets and crew battles are usually not useful for
+rating players. Events whose name or URL contains any of these keywords will not
+be included in the dataset. Enter \"none\" to include all events.
+
+Keywords to exclude, separated by commas (default {}): ",
+        if default.blocklist.is_empty() {
+            "none".to_owned()
+        } else {
+            default.blocklist.join(", ")
+        }
+    );
+    let blocklist = match read_string().as_str() {
+        "" => default.blocklist,
+        "none" => Vec::new(),
+        string => string
+            .split(',')
+            .map(|k| k.trim().to_lowercase())
+            .filter(|k| !k.is_empty())
+            .collect(),
+    };
+
+    print!(
+        "
+\x1b[1mMinimum Entrants\x1b[0m
+Events with fewer entrants than this will not be included in the dataset.
+
+Minimum entrants (default {}): ",
+        default.min_entrants
+    );
+    let min_entrants_input = read_string();
+    let min_entrants = if min_entrants_input.is_empty() {
+        default.min_entrants
+    } else {
+        min_entrants_input
+            .parse::<u64>()
+            .unwrap_or_else(|_| error("Not an integer", 1))
+    };
+
+    print!(
+        "
+\x1b[1mSingles Only\x1b[0m
+If enabled, events whose entrants are teams (such as doubles or crew battles)
+will not be included in the dataset.
+
+Include singles events only? (y/n, default {}) ",
+        if default.singles_only { "y" } else { "n" }
+    );
+    let singles_only = match read_string().chars().next() {
+        None => default.singles_only,
+        Some(c) => c == 'y',
+    };
+
+    EventRules {
+        blocklist,
+        min_entrants,
+        singles_only,
+    }
+}
+
+fn dataset_rules(connection: &Connection, name: Option<String>) {
+    let name = name.unwrap_or_else(|| {
+        print!("Dataset to change: ");
+        read_string()
+    });
+
+    let metadata = get_metadata(connection, &name)
+        .expect("Error communicating with SQLite")
+        .unwrap_or_else(|| error(&format!("Dataset {:?} does not exist", &name), 1));
+
+    let rules = read_event_rules(metadata.rules);
+
+    set_event_rules(connection, &name, &rules).expect("Error communicating with SQLite");
+
+    println!("\nUpdated event rules of {}", name);
+}
+
 fn dataset_delete(connection: &Connection, name: Option<String>) {
     let name = name.unwrap_or_else(|| {
         print!("Dataset to delete: ");
@@ -509,6 +1052,125 @@ fn dataset_rename(connection: &Connection, old: Option<String>, new: Option<Stri
     };
 }
 
+fn dataset_filter(connection: &Connection, name: &str, event: &str, include: bool, remove: bool) {
+    if get_metadata(connection, name)
+        .expect("Error communicating with SQLite")
+        .is_none()
+    {
+        error(&format!("Dataset {:?} does not exist", name), 1);
+    }
+
+    let slug = parse_event_slug(event)
+        .unwrap_or_else(|| error(&format!("{:?} is not a tournament or event", event), 1));
+
+    let list = if include { "include" } else { "exclude" };
+
+    if remove {
+        if !remove_event_filter(connection, name, &slug, include)
+            .expect("Error communicating with SQLite")
+        {
+            error(
+                &format!("{} is not on the {} list of {}", slug, list, name),
+                1,
+            );
+        }
+        println!("Removed {} from the {} list of {}", slug, list, name);
+        return;
+    }
+
+    set_event_filter(connection, name, &slug, include).expect("Error communicating with SQLite");
+
+    if include {
+        println!("Including {} in {}", slug, name);
+    } else {
+        println!("Excluding {} from {}", slug, name);
+    }
+}
+
+fn dataset_import_event(connection: &Connection, auth: String, name: &str, event: &str) {
+    let metadata = get_metadata(connection, name)
+        .expect("Error communicating with SQLite")
+        .unwrap_or_else(|| error(&format!("Dataset {:?} does not exist", name), 1));
+
+    let slug = parse_event_slug(event)
+        .filter(|slug| slug.contains("/event/"))
+        .unwrap_or_else(|| error(&format!("{:?} is not an event", event), 1));
+
+    let event_data = run_query::<EventInfo, _>(EventInfoVars { slug: &slug }, &auth)
+        .unwrap_or_else(|| error("Could not access start.gg", 1))
+        .unwrap_or_else(|| error(&format!("Event {:?} not found", slug), 1));
+
+    if event_synced(connection, name, event_data.id).expect("Error communicating with SQLite") {
+        issue(&format!("Event {} is already in {}", slug, name), 0);
+    }
+
+    import_event(connection, name, &metadata, &event_data, &auth)
+        .expect("Error communicating with SQLite");
+
+    println!("\nImported {} into {}", slug, name);
+    if event_data.time < metadata.last_sync {
+        println!(
+            "This event was rated after events that happened later; run 'startrnr dataset \
+recompute {:?}' to rate all sets in order",
+            name
+        );
+    }
+}
+
+fn dataset_recompute(
+    connection: &Connection,
+    name: Option<String>,
+    decay_const: Option<f64>,
+    var_const: Option<f64>,
+    weight_margin: Option<bool>,
+) {
+    let name = name.unwrap_or_else(|| {
+        print!("Dataset to recompute: ");
+        read_string()
+    });
+
+    let mut metadata = get_metadata(connection, &name)
+        .expect("Error communicating with SQLite")
+        .unwrap_or_else(|| error(&format!("Dataset {:?} does not exist", &name), 1));
+
+    if let Some(decay_const) = decay_const {
+        if !(0.0..=1.0).contains(&decay_const) {
+            error("Network decay constant is not between 0 and 1", 1);
+        }
+        metadata.decay_const = decay_const;
+    }
+    if let Some(var_const) = var_const {
+        metadata.var_const = var_const;
+    }
+    if let Some(weight_margin) = weight_margin {
+        metadata.weight_margin = weight_margin;
+    }
+
+    match sets_lost_by_rebuild(connection, &name, &metadata)
+        .expect("Error communicating with SQLite")
+    {
+        // Datasets synced by older versions do not have their sets stored
+        None => error(
+            "This dataset was synced before sets were stored locally, and cannot be recomputed",
+            1,
+        ),
+        Some(0) => (),
+        Some(missing) => {
+            print!(
+                "{} sets in this dataset are not stored locally and will be lost. Continue? (y/n) ",
+                missing
+            );
+            if !read_string().starts_with('y') {
+                return;
+            }
+        }
+    }
+
+    recompute_dataset(connection, &name, &metadata).expect("Error communicating with SQLite");
+
+    println!("\nRecomputed dataset {}", name);
+}
+
 // Players
 
 fn player_info(connection: &Connection, dataset: Option<String>, player: String) {
@@ -529,9 +1191,11 @@ fn player_info(connection: &Connection, dataset: Option<String>, player: String)
         print!("\x1b[2m{}\x1b[22m ", pre);
     }
     println!(
-        "\x1b[4m\x1b]8;;https://www.start.gg/user/{1}\x1b\\\
-\x1b[1m{0}\x1b[22m\x1b]8;;\x1b\\\x1b[0m ({1})",
-        name, discrim
+        "\x1b[4m\x1b]8;;{1}\x1b\\\
+\x1b[1m{0}\x1b[22m\x1b]8;;\x1b\\\x1b[0m ({2})",
+        name,
+        player_url(&discrim),
+        discrim.as_deref().unwrap_or("no account")
     );
     println!("\x1b[1mID:\x1b[0m {}", id.0);
 
@@ -541,6 +1205,123 @@ fn player_info(connection: &Connection, dataset: Option<String>, player: String)
         lost,
         (won as f64 / (won + lost) as f64) * 100.0
     );
+
+    let usage =
+        get_character_usage(connection, &dataset, id).expect("Error communicating with SQLite");
+    if !usage.is_empty() {
+        let total = usage.iter().map(|(_, games)| games).sum::<u64>();
+
+        println!("\n\x1b[1mCharacters:\x1b[0m");
+        for (character, games) in usage {
+            println!(
+                "  {}: {} games ({:.1}%)",
+                character,
+                games,
+                (games as f64 / total as f64) * 100.0
+            );
+        }
+    }
+}
+
+fn player_history(connection: &Connection, dataset: Option<String>, player: String, limit: u64) {
+    let dataset = dataset.unwrap_or_else(|| String::from("default"));
+
+    let player = get_player_from_input(connection, player)
+        .unwrap_or_else(|_| error("Could not find player", 1));
+
+    let history = get_player_history(connection, &dataset, player.id, limit)
+        .expect("Error communicating with SQLite");
+    if history.is_empty() {
+        issue(&format!("{} has no sets in {}", player.name, dataset), 0);
+    }
+
+    for PlayerSetRecord {
+        set,
+        team,
+        event,
+        tournament,
+    } in history
+    {
+        let time = set.completed.or(set.time).unwrap();
+        print!(
+            "\x1b[2m{}\x1b[0m  ",
+            Utc.timestamp_opt(time.0 as i64, 0)
+                .unwrap()
+                .format("%m/%d/%Y")
+        );
+
+        let (result, color) = if set.dq {
+            ("DQ", "2")
+        } else if set.winner == team {
+            ("W", "92")
+        } else {
+            ("L", "91")
+        };
+        print!("\x1b[1m\x1b[{}m{}\x1b[0m ", color, result);
+        if let Some(scores) = &set.scores {
+            let opponent = scores.iter().enumerate().find(|&(i, _)| i != team);
+            if let (Some(score), Some((_, opponent))) = (scores.get(team), opponent) {
+                print!("{}-{} ", score, opponent);
+            }
+        }
+
+        let opponents = set
+            .teams
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != team)
+            .flat_map(|(_, members)| members.iter().map(|p| &p.name[..]))
+            .collect::<Vec<_>>();
+        print!("vs \x1b[1m{}\x1b[0m", opponents.join(" / "));
+
+        match tournament {
+            Some(tournament) => {
+                print!("  {} - {}", tournament.name, event);
+                let location = [tournament.city, tournament.state, tournament.country]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>();
+                if tournament.online {
+                    print!(" \x1b[2m(online)\x1b[0m");
+                } else if !location.is_empty() {
+                    print!(" \x1b[2m({})\x1b[0m", location.join(", "));
+                }
+                println!();
+            }
+            None => println!("  {}", event),
+        }
+    }
+}
+
+fn player_link(connection: &Connection, player: String, account: String) {
+    let player = get_player_from_input(connection, player)
+        .unwrap_or_else(|_| error("Could not find player", 1));
+
+    if let Some(discrim) = player.discrim {
+        error(
+            &format!("{} is already linked to account {}", player.name, discrim),
+            1,
+        );
+    }
+    if account.is_empty() || !account.chars().all(|c| c.is_ascii_hexdigit()) {
+        error(&format!("{:?} is not a user discriminator", account), 1);
+    }
+
+    match link_player(connection, player.id, &account).expect("Error communicating with SQLite") {
+        None => println!("\nLinked {} to account {}", player.name, account),
+        Some((linked, not_recomputed)) => {
+            println!(
+                "\nMerged {} (ID {}) into {} (ID {})",
+                player.name, player.id.0, linked.name, linked.id.0
+            );
+            for dataset in not_recomputed {
+                println!(
+                    "Dataset {} was not recomputed, as some of its sets are not stored locally",
+                    dataset
+                );
+            }
+        }
+    }
 }
 
 fn player_matchup(
@@ -548,6 +1329,7 @@ fn player_matchup(
     dataset: Option<String>,
     player1: String,
     player2: String,
+    characters: bool,
 ) {
     let dataset = dataset.unwrap_or_else(|| String::from("default"));
 
@@ -599,17 +1381,19 @@ fn player_matchup(
         print!("\x1b[2m{}\x1b[22m ", pre);
     }
     print!(
-        "\x1b[4m\x1b]8;;https://www.start.gg/user/{}\x1b\\\
+        "\x1b[4m\x1b]8;;{}\x1b\\\
 \x1b[1m{}\x1b[22m\x1b]8;;\x1b\\\x1b[0m - ",
-        discrim1, name1
+        player_url(&discrim1),
+        name1
     );
     if let Some(pre) = prefix2 {
         print!("\x1b[2m{}\x1b[22m ", pre);
     }
     println!(
-        "\x1b[4m\x1b]8;;https://www.start.gg/user/{}\x1b\\\
+        "\x1b[4m\x1b]8;;{}\x1b\\\
 \x1b[1m{}\x1b[22m\x1b]8;;\x1b\\\x1b[0m",
-        discrim2, name2
+        player_url(&discrim2),
+        name2
     );
 
     // Probability breakdown
@@ -648,11 +1432,123 @@ fn player_matchup(
             (b as f64 / (a + b) as f64) * 100.0
         );
     }
+
+    if characters {
+        let matchups = get_character_matchups(connection, &dataset, player1, player2)
+            .expect("Error communicating with SQLite");
+
+        if matchups.is_empty() {
+            println!("\nNo character data for this matchup");
+        } else {
+            println!("\n\x1b[1mGames by Character:\x1b[0m");
+            for (character1, character2, won, lost) in matchups {
+                println!("  {} vs. {}: {} - {}", character1, character2, won, lost);
+            }
+        }
+    }
+}
+
+fn team_matchup(
+    connection: &Connection,
+    dataset: Option<String>,
+    team1: Vec<String>,
+    team2: Vec<String>,
+) {
+    let dataset = dataset.unwrap_or_else(|| String::from("default"));
+
+    let metadata = get_metadata(connection, &dataset)
+        .expect("Error communicating with SQLite")
+        .unwrap_or_else(|| error("Dataset not found", 1));
+
+    let team1 = team1
+        .into_iter()
+        .map(|player| {
+            get_player_from_input(connection, player)
+                .unwrap_or_else(|_| error("Could not find player", 1))
+        })
+        .collect::<Vec<_>>();
+    let team2 = team2
+        .into_iter()
+        .map(|player| {
+            get_player_from_input(connection, player)
+                .unwrap_or_else(|_| error("Could not find player", 1))
+        })
+        .collect::<Vec<_>>();
+
+    // The advantage between two teams is the average advantage over every
+    // pairing of opponents, as when rating team sets
+    let num_pairs = (team1.len() * team2.len()) as f64;
+    let mut hypothetical = false;
+    let mut advantage = 0.0;
+    let mut variance = 0.0;
+    for player1 in team1.iter() {
+        for player2 in team2.iter() {
+            let (adv, var) = get_network_data(connection, &dataset, player1.id, player2.id)
+                .expect("Error communicating with SQLite")
+                .unwrap_or_else(|| {
+                    hypothetical = true;
+                    hypothetical_advantage(
+                        connection,
+                        &dataset,
+                        player1.id,
+                        player2.id,
+                        metadata.decay_const,
+                    )
+                    .expect("Error communicating with SQLite")
+                });
+            advantage += adv / num_pairs;
+            variance += var / num_pairs;
+        }
+    }
+
+    let probability = 1.0 / (1.0 + f64::exp(-advantage));
+
+    let (color, other_color) = ansi_num_color(advantage, 0.2, 2.0);
+
+    let team_names = |team: &Vec<PlayerData>| {
+        team.iter()
+            .map(|player| player.name.clone())
+            .collect::<Vec<_>>()
+            .join(" & ")
+    };
+    let names1 = team_names(&team1);
+    let names2 = team_names(&team2);
+
+    println!("\x1b[1m{}\x1b[0m - \x1b[1m{}\x1b[0m", names1, names2);
+
+    // Probability breakdown
+
+    println!(
+        "\x1b[1m\x1b[{4}m{0:>2$}\x1b[0m - \x1b[1m\x1b[{5}m{1:<3$}\x1b[0m",
+        format!("{:.1}%", (1.0 - probability) * 100.0),
+        format!("{:.1}%", probability * 100.0),
+        names1.len(),
+        names2.len(),
+        other_color,
+        color
+    );
+
+    // Advantage + variance
+
+    println!(
+        "\n\x1b[1m{0}Advantage: \x1b[{1}m{2:+.4}\x1b[39m\n{0}Variance: {3:.4}\x1b[0m",
+        if hypothetical { "Hypothetical " } else { "" },
+        color,
+        advantage,
+        variance
+    );
 }
 
 // Sync
 
-fn sync(connection: &Connection, auth: String, datasets: Vec<String>, all: bool) {
+fn sync(
+    connection: &Connection,
+    auth: String,
+    datasets: Vec<String>,
+    all: bool,
+    dry_run: bool,
+    lookback: u64,
+) {
     let all_datasets = list_dataset_names(connection).unwrap();
 
     let datasets = if all {
@@ -687,10 +1583,81 @@ fn sync(connection: &Connection, auth: String, datasets: Vec<String>, all: bool)
             .map(|end| min(end, current_time))
             .unwrap_or(current_time);
 
-        sync_dataset(connection, &dataset, dataset_metadata, before, &auth)
+        if dry_run {
+            preview_sync(connection, &dataset, &dataset_metadata, before, &auth)
+                .expect("Error communicating with SQLite");
+        } else {
+            sync_dataset(
+                connection,
+                &dataset,
+                dataset_metadata,
+                before,
+                lookback * SECS_IN_DAY,
+                &auth,
+            )
             .expect("Error communicating with SQLite");
+        }
+    }
+}
 
-        update_last_sync(connection, &dataset, before).expect("Error communicating with SQLite");
+// Cache
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn cache_info(cache_dir: &Path) {
+    let stats = cache_stats(cache_dir).unwrap_or_else(|_| error("Could not read cache", 2));
+
+    println!("\x1b[1mCache directory:\x1b[0m {}", cache_dir.display());
+
+    if stats.is_empty() {
+        println!("\nThe cache is empty.");
+        return;
+    }
+
+    println!();
+    for CacheStats {
+        query,
+        entries,
+        size,
+    } in stats.iter()
+    {
+        println!(
+            "· \x1b[1m{}\x1b[0m: {} responses ({})",
+            query,
+            entries,
+            format_size(*size)
+        );
+    }
+
+    println!(
+        "\n\x1b[1mTotal:\x1b[0m {} responses ({})",
+        stats.iter().map(|s| s.entries).sum::<u64>(),
+        format_size(stats.iter().map(|s| s.size).sum())
+    );
+}
+
+fn cache_purge(cache_dir: &Path, query: Option<String>) {
+    purge_cache(cache_dir, query.as_deref())
+        .unwrap_or_else(|_| error("Could not delete cached responses", 2));
+
+    match query {
+        Some(query) => println!("Purged cached {} responses", query),
+        None => println!("Purged response cache"),
     }
 }
 
@@ -699,7 +1666,7 @@ fn ranking_create(connection: &Connection, dataset: Option<String>) {
 
     let dataset = dataset.unwrap_or_else(|| String::from("default"));
 
-    let metadata = get_metadata(connection, &dataset)
+    get_metadata(connection, &dataset)
         .expect("Error communicating with SQLite")
         .unwrap_or_else(|| error("Dataset not found", 1));
 
@@ -0,0 +1,246 @@
+use crate::queries::{requests_made, EventData};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::{self, IsTerminal, Write};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+// Log format
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human-readable progress
+    #[default]
+    Text,
+    /// One JSON record per line
+    Json,
+}
+
+static LOG_FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+/// Set the format that syncing progress is reported in.
+pub fn set_log_format(format: LogFormat) {
+    let _ = LOG_FORMAT.set(format);
+}
+
+fn log_format() -> LogFormat {
+    LOG_FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Print a status message. When logging JSON, status messages are written to
+/// stderr so that stdout only contains log records.
+pub fn status(msg: &str) {
+    match log_format() {
+        LogFormat::Text => println!("{}", msg),
+        LogFormat::Json => eprintln!("{}", msg),
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h {:02}m", secs / 3600, secs / 60 % 60)
+    } else if secs >= 60 {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+// Sync progress
+
+/// What happened to the sets of a single event.
+#[derive(Debug, Default)]
+pub struct EventStats {
+    pub rated: usize,
+    /// Sets that were not rated, by reason
+    pub skipped: BTreeMap<&'static str, usize>,
+}
+
+impl EventStats {
+    pub fn skip(&mut self, reason: &'static str) {
+        *self.skipped.entry(reason).or_insert(0) += 1;
+    }
+}
+
+fn describe_skip_reason(reason: &str) -> &str {
+    match reason {
+        "dq" => "DQ'd or forfeited",
+        "teams" => "team",
+        "format" => "free-for-all or incomplete",
+        _ => reason,
+    }
+}
+
+/// Progress display and log for syncing a dataset.
+pub struct SyncProgress<'a> {
+    dataset: &'a str,
+    total: usize,
+    done: usize,
+    // Events synced by this run, used to estimate the time remaining
+    processed: usize,
+    sets: EventStats,
+    skipped_events: Vec<(String, String)>,
+    start: Instant,
+    start_requests: u64,
+    format: LogFormat,
+    out: Box<dyn Write + 'a>,
+    terminal: bool,
+}
+
+impl<'a> SyncProgress<'a> {
+    pub fn new(dataset: &'a str, total: usize) -> Self {
+        SyncProgress {
+            terminal: io::stdout().is_terminal(),
+            ..Self::with_output(dataset, total, log_format(), Box::new(io::stdout()))
+        }
+    }
+
+    /// Report progress in the given format to something other than stdout.
+    pub fn with_output(
+        dataset: &'a str,
+        total: usize,
+        format: LogFormat,
+        out: Box<dyn Write + 'a>,
+    ) -> Self {
+        SyncProgress {
+            dataset,
+            total,
+            done: 0,
+            processed: 0,
+            sets: EventStats::default(),
+            skipped_events: Vec::new(),
+            start: Instant::now(),
+            start_requests: requests_made(),
+            format,
+            out,
+            terminal: false,
+        }
+    }
+
+    fn print(&mut self, line: impl Display) {
+        let _ = writeln!(self.out, "{}", line);
+    }
+
+    /// Record an event that was synced by an earlier, interrupted run.
+    pub fn already_synced(&mut self) {
+        self.done += 1;
+    }
+
+    pub fn event_skipped(&mut self, event: &EventData, reason: String) {
+        self.done += 1;
+
+        if self.format == LogFormat::Json {
+            self.print(json!({
+                "type": "event",
+                "dataset": self.dataset,
+                "id": event.id.0,
+                "slug": event.slug,
+                "excluded": reason,
+            }));
+        }
+        self.skipped_events.push((event.slug.clone(), reason));
+    }
+
+    pub fn event_synced(&mut self, event: &EventData, stats: EventStats, duration: Duration) {
+        self.done += 1;
+        self.processed += 1;
+
+        match self.format {
+            LogFormat::Json => self.print(json!({
+                "type": "event",
+                "dataset": self.dataset,
+                "id": event.id.0,
+                "slug": event.slug,
+                "sets": stats.rated,
+                "skipped": stats.skipped,
+                "duration": duration.as_secs_f64(),
+            })),
+            LogFormat::Text if !self.terminal => self.print(format!(
+                "Synced {} ({}/{}): {} sets",
+                event.slug, self.done, self.total, stats.rated
+            )),
+            LogFormat::Text => (),
+        }
+
+        self.sets.rated += stats.rated;
+        for (reason, count) in stats.skipped {
+            *self.sets.skipped.entry(reason).or_insert(0) += count;
+        }
+    }
+
+    /// Redraw the progress bar, if the output is a terminal.
+    pub fn draw(&self) {
+        if self.format != LogFormat::Text || !self.terminal {
+            return;
+        }
+
+        const WIDTH: usize = 30;
+        let filled = (self.done * WIDTH).checked_div(self.total).unwrap_or(WIDTH);
+
+        let eta = if self.processed == 0 {
+            String::from("--")
+        } else {
+            let remaining = (self.total - self.done) as u32;
+            format_duration(self.start.elapsed() / self.processed as u32 * remaining)
+        };
+
+        print!(
+            "\r\x1b[2K[{:=<filled$}{:<empty$}] {}/{} events, {} sets, {} requests, ETA {}",
+            "",
+            "",
+            self.done,
+            self.total,
+            self.sets.rated,
+            requests_made() - self.start_requests,
+            eta,
+            filled = filled,
+            empty = WIDTH - filled,
+        );
+        let _ = io::stdout().flush();
+    }
+
+    pub fn finish(mut self) {
+        let duration = self.start.elapsed();
+        let requests = requests_made() - self.start_requests;
+
+        if self.format == LogFormat::Json {
+            self.print(json!({
+                "type": "sync",
+                "dataset": self.dataset,
+                "events": self.processed,
+                "events_skipped": self.skipped_events.len(),
+                "sets": self.sets.rated,
+                "skipped": self.sets.skipped,
+                "requests": requests,
+                "duration": duration.as_secs_f64(),
+            }));
+            return;
+        }
+
+        if self.terminal {
+            self.print("");
+        }
+        self.print(format!(
+            "Rated {} sets from {} events in {} ({} requests)",
+            self.sets.rated,
+            self.processed,
+            format_duration(duration),
+            requests
+        ));
+        for (reason, count) in self.sets.skipped.clone() {
+            self.print(format!(
+                "Excluded {} {} sets",
+                count,
+                describe_skip_reason(reason)
+            ));
+        }
+        if !self.skipped_events.is_empty() {
+            self.print(format!("Skipped {} events:", self.skipped_events.len()));
+            for (slug, reason) in std::mem::take(&mut self.skipped_events) {
+                self.print(format!("  {} ({})", slug, reason));
+            }
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use cynic::{GraphQlResponse, QueryBuilder};
 use serde::{Deserialize, Serialize};
+use std::cmp::min;
+use std::collections::VecDeque;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub mod scalars;
 pub use scalars::*;
@@ -12,9 +16,15 @@ pub mod tournament_events;
 pub use tournament_events::*;
 pub mod event_sets;
 pub use event_sets::*;
+pub mod event_info;
+pub use event_info::*;
 pub mod player_info;
 pub use player_info::*;
 
+#[cfg(test)]
+pub mod mock;
+
+use crate::cache::CacheEntry;
 use crate::error;
 
 // Auth key
@@ -44,12 +54,196 @@ pub fn get_auth_token(config_dir: &Path) -> String {
     }
 }
 
+// API endpoint
+
+pub const DEFAULT_ENDPOINT: &str = "https://api.start.gg/gql/alpha";
+
+static ENDPOINT: OnceLock<String> = OnceLock::new();
+
+pub fn get_endpoint(endpoint: Option<String>, config_dir: &Path) -> String {
+    use std::env::var;
+    use std::fs::read_to_string;
+
+    endpoint
+        .or_else(|| var("STARTGG_ENDPOINT").ok())
+        .or_else(|| {
+            let mut endpoint_file = config_dir.to_owned();
+            endpoint_file.push("endpoint.txt");
+            read_to_string(endpoint_file).ok()
+        })
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| DEFAULT_ENDPOINT.to_owned())
+}
+
+/// Set the endpoint that all queries are sent to.
+pub fn set_endpoint(endpoint: String) {
+    let _ = ENDPOINT.set(endpoint);
+}
+
+fn endpoint() -> &'static str {
+    ENDPOINT.get().map_or(DEFAULT_ENDPOINT, String::as_str)
+}
+
 // Query machinery
 
 pub trait QueryUnwrap<Vars>: 'static + QueryBuilder<Vars> {
     type Unwrapped;
 
     fn unwrap_response(response: GraphQlResponse<Self>) -> Option<Self::Unwrapped>;
+
+    /// Whether this response can never change, and so can be stored in the
+    /// response cache.
+    fn cacheable(_response: &GraphQlResponse<Self>) -> bool {
+        false
+    }
+}
+
+// The name of a query type, used to identify its responses in the cache
+fn query_name<Builder>() -> &'static str {
+    std::any::type_name::<Builder>()
+        .rsplit("::")
+        .next()
+        .unwrap()
+}
+
+// Rate limiting
+
+// start.gg allows 80 requests per minute for each auth token
+const REQUESTS_PER_WINDOW: usize = 80;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+const MIN_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(120);
+const MAX_ATTEMPTS: u32 = 10;
+
+/// Request budget shared by every query made by the program.
+///
+/// Requests are sent at full speed until the per-minute budget is used up.
+/// Whenever start.gg reports that the rate limit was exceeded, all requests
+/// are paused for an exponentially increasing amount of time.
+struct RateLimiter {
+    requests: VecDeque<Instant>,
+    backoff: Duration,
+    paused_until: Option<Instant>,
+    /// Whether the per-minute budget applies, which it does not for servers
+    /// other than start.gg, such as the mock server used in tests
+    limit_requests: bool,
+}
+
+impl RateLimiter {
+    const fn new() -> Self {
+        RateLimiter {
+            requests: VecDeque::new(),
+            backoff: Duration::ZERO,
+            paused_until: None,
+            limit_requests: true,
+        }
+    }
+
+    /// Reserve a slot for a request at time `now`, or return how long to wait
+    /// until one might be available.
+    fn reserve(&mut self, now: Instant) -> Option<Duration> {
+        if let Some(until) = self.paused_until {
+            if until > now {
+                return Some(until - now);
+            }
+            self.paused_until = None;
+        }
+
+        while self
+            .requests
+            .front()
+            .is_some_and(|&time| now.duration_since(time) >= RATE_LIMIT_WINDOW)
+        {
+            self.requests.pop_front();
+        }
+
+        if !self.limit_requests {
+            None
+        } else if self.requests.len() < REQUESTS_PER_WINDOW {
+            self.requests.push_back(now);
+            None
+        } else {
+            Some(self.requests[0] + RATE_LIMIT_WINDOW - now)
+        }
+    }
+
+    /// Pause all requests from time `now`, and return for how long.
+    fn back_off(&mut self, now: Instant) -> Duration {
+        self.backoff = if self.backoff.is_zero() {
+            MIN_BACKOFF
+        } else {
+            min(self.backoff * 2, MAX_BACKOFF)
+        };
+        self.paused_until = Some(now + self.backoff);
+        self.backoff
+    }
+
+    fn succeeded(&mut self) {
+        self.backoff = Duration::ZERO;
+    }
+}
+
+static RATE_LIMITER: Mutex<RateLimiter> = Mutex::new(RateLimiter::new());
+
+/// Send requests without waiting for start.gg's per-minute budget.
+#[cfg(test)]
+pub fn disable_rate_limit() {
+    RATE_LIMITER.lock().unwrap().limit_requests = false;
+}
+
+static REQUESTS_MADE: AtomicU64 = AtomicU64::new(0);
+
+/// The number of requests sent to start.gg so far, not counting responses
+/// served from the cache.
+pub fn requests_made() -> u64 {
+    REQUESTS_MADE.load(Ordering::Relaxed)
+}
+
+fn wait_for_rate_limit() {
+    loop {
+        // The lock must not be held while sleeping
+        let wait = RATE_LIMITER.lock().unwrap().reserve(Instant::now());
+        match wait {
+            Some(duration) => sleep(duration),
+            None => break,
+        }
+    }
+}
+
+fn back_off(reason: &str) {
+    let wait = RATE_LIMITER.lock().unwrap().back_off(Instant::now());
+    eprintln!("  ({}, retrying in {}s)", reason, wait.as_secs());
+}
+
+fn is_rate_limit_error<T>(response: &GraphQlResponse<T>) -> bool {
+    response.errors.as_ref().is_some_and(|errors| {
+        errors
+            .iter()
+            .any(|err| err.message.to_lowercase().contains("rate limit"))
+    })
+}
+
+// start.gg rejects queries that would return more than 1000 objects with this
+// error
+const COMPLEXITY_ERROR: &str = "Your query complexity is too high";
+
+fn is_complexity_error<T>(response: &GraphQlResponse<T>) -> bool {
+    response.errors.as_ref().is_some_and(|errors| {
+        errors
+            .iter()
+            .any(|err| err.message.starts_with(COMPLEXITY_ERROR))
+    })
+}
+
+/// Why a query could not be completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryError {
+    /// The query asked for too many objects at once
+    TooComplex,
+    /// start.gg could not be reached, or returned no usable data
+    Failed,
 }
 
 // Generic function for running start.gg queries
@@ -59,24 +253,223 @@ where
     Builder: QueryUnwrap<Vars>,
     for<'de> Builder: Deserialize<'de>,
 {
-    use cynic::http::ReqwestBlockingExt;
+    try_query::<Builder, Vars>(vars, auth_token).ok()
+}
+
+/// Run a start.gg query, reporting why it failed if it did.
+pub fn try_query<Builder, Vars>(
+    vars: Vars,
+    auth_token: &str,
+) -> Result<Builder::Unwrapped, QueryError>
+where
+    Vars: Copy + Serialize,
+    Builder: QueryUnwrap<Vars>,
+    for<'de> Builder: Deserialize<'de>,
+{
+    use reqwest::StatusCode;
+
+    let operation = Builder::build(vars);
+    let cache_entry = CacheEntry::new(query_name::<Builder>(), &operation.query, &vars);
+    if let Some(cached) = cache_entry.as_ref().and_then(CacheEntry::read) {
+        if let Ok(response) = serde_json::from_str::<GraphQlResponse<Builder>>(&cached) {
+            return Builder::unwrap_response(response).ok_or(QueryError::Failed);
+        }
+    }
+
+    let client = reqwest::blocking::Client::new();
+
+    for _ in 0..MAX_ATTEMPTS {
+        wait_for_rate_limit();
+        REQUESTS_MADE.fetch_add(1, Ordering::Relaxed);
+
+        let response = match client
+            .post(endpoint())
+            .header("Authorization", String::from("Bearer ") + auth_token)
+            .json(&operation)
+            .send()
+        {
+            Ok(response) => response,
+            Err(_) => {
+                back_off("Could not reach start.gg");
+                continue;
+            }
+        };
+
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            back_off("Rate limit exceeded");
+            continue;
+        }
+
+        let text = match response.text() {
+            Ok(text) => text,
+            Err(_) => {
+                back_off("Could not reach start.gg");
+                continue;
+            }
+        };
+        let response = match serde_json::from_str::<GraphQlResponse<Builder>>(&text) {
+            Ok(response) => response,
+            Err(_) => {
+                back_off("Invalid response from start.gg");
+                continue;
+            }
+        };
+
+        if is_rate_limit_error(&response) {
+            back_off("Rate limit exceeded");
+            continue;
+        }
+
+        RATE_LIMITER.lock().unwrap().succeeded();
+
+        if is_complexity_error(&response) {
+            return Err(QueryError::TooComplex);
+        }
+
+        if response.errors.is_none() && Builder::cacheable(&response) {
+            if let Some(entry) = cache_entry {
+                // Failing to cache a response is not fatal
+                let _ = entry.write(&text);
+            }
+        }
+
+        return Builder::unwrap_response(response).ok_or(QueryError::Failed);
+    }
+
+    Err(QueryError::Failed)
+}
+
+// Pagination
+
+/// start.gg does not return any result past the 10,000th of a paginated query.
+pub const MAX_RESULTS: u64 = 10000;
+
+/// A single page of results.
+pub struct Page<T> {
+    /// Number of results across all pages
+    pub total: u64,
+    /// Results on this page, with `None` for any that could not be read, so
+    /// that the position in the query is known
+    pub items: Vec<Option<T>>,
+}
+
+/// The results of a paginated query.
+pub struct Pages<T> {
+    /// Number of results across all pages, which is larger than the number of
+    /// items if the query could not be fetched in full
+    pub total: u64,
+    pub items: Vec<T>,
+}
+
+/// Fetch the pages of a paginated query.
+///
+/// `fetch` is called with a page number and a page size. If start.gg rejects a
+/// page as too complex, `per_page` is halved and fetching continues at the
+/// smaller size, which later queries sharing `per_page` then start from.
+///
+/// If the query has more results than start.gg will return, only the first
+/// page is fetched so that the caller can narrow the query, unless `truncate`
+/// is set, in which case every result up to the cap is fetched.
+pub fn fetch_pages<T, F>(per_page: &AtomicU64, truncate: bool, mut fetch: F) -> Option<Pages<T>>
+where
+    F: FnMut(i32, i32) -> Result<Page<T>, QueryError>,
+{
+    let mut items = Vec::new();
+    let mut total = None;
+    // Results fetched so far, including those that could not be read
+    let mut done = 0;
 
-    let mut response = reqwest::blocking::Client::new()
-        .post("https://api.start.gg/gql/alpha")
-        .header("Authorization", String::from("Bearer ") + auth_token)
-        .run_graphql(Builder::build(vars));
+    loop {
+        if let Some(total) = total {
+            if done >= total || done >= MAX_RESULTS || (total > MAX_RESULTS && !truncate) {
+                break;
+            }
+        }
 
-    // If query fails to reach server, retry up to 10 times
-    for _ in 1..10 {
-        if response.is_ok() {
+        // The page size may have changed since the last page, in which case
+        // the new page can overlap with the results so far
+        let size = per_page.load(Ordering::Relaxed);
+        let page = done / size + 1;
+        if (page - 1) * size >= MAX_RESULTS {
             break;
         }
-        sleep(Duration::from_secs(2));
-        response = reqwest::blocking::Client::new()
-            .post("https://api.start.gg/gql/alpha")
-            .header("Authorization", String::from("Bearer ") + auth_token)
-            .run_graphql(Builder::build(vars));
+
+        match fetch(page as i32, size as i32) {
+            Ok(response) => {
+                total = Some(response.total);
+
+                // Stop if the page has nothing new, such as when the results
+                // have changed since the query started
+                let skip = done % size;
+                let count = response.items.len() as u64;
+                if count <= skip {
+                    break;
+                }
+                done += count - skip;
+                items.extend(response.items.into_iter().skip(skip as usize).flatten());
+            }
+            Err(QueryError::TooComplex) if size > 1 => {
+                // Another thread may have reduced the page size already
+                let _ =
+                    per_page.compare_exchange(size, size / 2, Ordering::Relaxed, Ordering::Relaxed);
+            }
+            Err(_) => return None,
+        }
+    }
+
+    Some(Pages {
+        total: total.unwrap_or(0),
+        items,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit_window() {
+        let mut limiter = RateLimiter::new();
+        let start = Instant::now();
+
+        for _ in 0..REQUESTS_PER_WINDOW {
+            assert_eq!(limiter.reserve(start), None);
+        }
+        assert_eq!(limiter.reserve(start), Some(RATE_LIMIT_WINDOW));
+
+        // Slots free up once the earliest requests leave the window
+        let later = start + Duration::from_secs(45);
+        assert_eq!(limiter.reserve(later), Some(Duration::from_secs(15)));
+        assert_eq!(limiter.reserve(start + RATE_LIMIT_WINDOW), None);
+
+        let mut limiter = RateLimiter::new();
+        limiter.limit_requests = false;
+        for _ in 0..=REQUESTS_PER_WINDOW {
+            assert_eq!(limiter.reserve(start), None);
+        }
     }
 
-    Builder::unwrap_response(response.ok()?)
+    #[test]
+    fn rate_limit_backoff() {
+        let mut limiter = RateLimiter::new();
+        let start = Instant::now();
+
+        assert_eq!(limiter.back_off(start), MIN_BACKOFF);
+        assert_eq!(limiter.reserve(start), Some(MIN_BACKOFF));
+        assert_eq!(limiter.reserve(start + MIN_BACKOFF), None);
+
+        // Repeated failures double the pause, up to a limit
+        assert_eq!(limiter.back_off(start), MIN_BACKOFF * 2);
+        (0..10).for_each(|_| {
+            limiter.back_off(start);
+        });
+        assert_eq!(limiter.back_off(start), MAX_BACKOFF);
+
+        // The pause applies even when the per-minute budget does not
+        limiter.limit_requests = false;
+        assert_eq!(limiter.reserve(start), Some(MAX_BACKOFF));
+
+        limiter.succeeded();
+        assert_eq!(limiter.back_off(start), MIN_BACKOFF);
+    }
 }
@@ -0,0 +1,93 @@
+use super::scalars::*;
+use super::{EventData, QueryUnwrap, TournamentInfo};
+use cynic::GraphQlResponse;
+use schema::schema;
+
+// Variables
+
+#[derive(cynic::QueryVariables, Debug, Copy, Clone)]
+pub struct EventInfoVars<'a> {
+    pub slug: &'a str,
+}
+
+// Query
+
+#[derive(cynic::QueryFragment, Debug)]
+#[cynic(graphql_type = "Query", variables = "EventInfoVars")]
+pub struct EventInfo {
+    #[arguments(slug: $slug)]
+    event: Option<Event>,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+struct Event {
+    id: Option<EventId>,
+    slug: Option<String>,
+    name: Option<String>,
+    start_at: Option<Timestamp>,
+    is_online: Option<bool>,
+    num_entrants: Option<i32>,
+    #[cynic(rename = "type")]
+    event_type: Option<i32>,
+    videogame: Option<Videogame>,
+    tournament: Option<Tournament>,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+struct Videogame {
+    id: Option<VideogameId>,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+struct Tournament {
+    id: Option<TournamentId>,
+    name: Option<String>,
+    slug: Option<String>,
+    start_at: Option<Timestamp>,
+    city: Option<String>,
+    addr_state: Option<String>,
+    country_code: Option<String>,
+    is_online: Option<bool>,
+    num_attendees: Option<i32>,
+}
+
+// Unwrap
+
+impl<'a> QueryUnwrap<EventInfoVars<'a>> for EventInfo {
+    // None if no event has the given slug
+    type Unwrapped = Option<EventData>;
+
+    fn unwrap_response(response: GraphQlResponse<EventInfo>) -> Option<Option<EventData>> {
+        let event = response.data?.event;
+
+        Some(event.and_then(|event| {
+            let tournament = event.tournament.and_then(|tour| {
+                Some(TournamentInfo {
+                    id: tour.id?,
+                    name: tour.name.unwrap_or_default(),
+                    slug: tour.slug?,
+                    time: tour.start_at?,
+                    city: tour.city,
+                    state: tour.addr_state,
+                    country: tour.country_code,
+                    online: tour.is_online.unwrap_or(false),
+                    entrants: tour.num_attendees.unwrap_or(0) as u64,
+                })
+            });
+            Some(EventData {
+                id: event.id?,
+                slug: event.slug?,
+                name: event.name.unwrap_or_default(),
+                time: event.start_at?,
+                online: event
+                    .is_online
+                    .or(tournament.as_ref().map(|tour| tour.online))
+                    .unwrap_or(false),
+                entrants: event.num_entrants.unwrap_or(0) as u64,
+                singles: event.event_type.is_none_or(|t| t == 1),
+                game: event.videogame.and_then(|game| game.id),
+                tournament,
+            })
+        }))
+    }
+}
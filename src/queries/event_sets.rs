@@ -8,9 +8,11 @@ pub type Teams<T> = Vec<Vec<T>>;
 // Variables
 
 #[derive(cynic::QueryVariables, Debug, Copy, Clone)]
-pub struct EventSetsVars {
+pub struct EventSetsVars<'a> {
     pub event: EventId,
     pub page: i32,
+    pub per_page: i32,
+    pub phase_groups: Option<&'a [PhaseGroupId]>,
 }
 
 // Query
@@ -25,10 +27,23 @@ pub struct EventSets {
 #[derive(cynic::QueryFragment, Debug)]
 #[cynic(variables = "EventSetsVars")]
 struct Event {
-    #[arguments(page: $page, perPage: 25, sortType: RECENT)]
+    state: Option<ActivityState>,
+    #[cynic(flatten)]
+    phase_groups: Vec<PhaseGroup>,
+    #[arguments(
+        page: $page,
+        perPage: $per_page,
+        sortType: RECENT,
+        filters: { phaseGroupIds: $phase_groups }
+    )]
     sets: Option<SetConnection>,
 }
 
+#[derive(cynic::QueryFragment, Debug)]
+struct PhaseGroup {
+    id: Option<PhaseGroupId>,
+}
+
 #[derive(cynic::QueryFragment, Debug)]
 struct SetConnection {
     page_info: Option<PageInfo>,
@@ -38,7 +53,7 @@ struct SetConnection {
 
 #[derive(cynic::QueryFragment, Debug)]
 struct PageInfo {
-    total_pages: Option<i32>,
+    total: Option<i32>,
 }
 
 #[derive(cynic::QueryFragment, Debug)]
@@ -46,15 +61,62 @@ struct Set {
     id: Option<SetId>,
     start_at: Option<Timestamp>,
     started_at: Option<Timestamp>,
+    completed_at: Option<Timestamp>,
+    round: Option<i32>,
+    display_score: Option<String>,
     #[arguments(includeByes: true)]
     #[cynic(flatten)]
     slots: Vec<SetSlot>,
     winner_id: Option<i32>,
+    #[cynic(flatten)]
+    games: Vec<Game>,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+struct Game {
+    order_num: Option<i32>,
+    winner_id: Option<i32>,
+    #[cynic(flatten)]
+    selections: Vec<GameSelection>,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+struct GameSelection {
+    entrant: Option<SelectionEntrant>,
+    character: Option<Character>,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+#[cynic(graphql_type = "Entrant")]
+struct SelectionEntrant {
+    id: Option<EntrantId>,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+struct Character {
+    id: Option<CharacterId>,
+    name: Option<String>,
 }
 
 #[derive(cynic::QueryFragment, Debug)]
 struct SetSlot {
     entrant: Option<Entrant>,
+    standing: Option<Standing>,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+struct Standing {
+    stats: Option<StandingStats>,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+struct StandingStats {
+    score: Option<Score>,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+struct Score {
+    value: Option<f64>,
 }
 
 #[derive(cynic::QueryFragment, Debug)]
@@ -85,29 +147,54 @@ struct User {
 // Unwrap
 
 pub struct EventSetsResponse {
-    pub pages: u64,
-    pub sets: Vec<SetData>,
+    // Number of sets across all pages
+    pub total: u64,
+    pub phase_groups: Vec<PhaseGroupId>,
+    // Sets that could not be read are `None`
+    pub sets: Vec<Option<SetData>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CharacterData {
+    pub id: CharacterId,
+    pub name: String,
+}
+
+#[derive(Debug)]
+pub struct GameData {
+    pub number: u64,
+    pub winner: Option<usize>,
+    // Characters played by each player in a singles set
+    pub selections: Vec<(PlayerId, CharacterData)>,
 }
 
 #[derive(Debug)]
 pub struct SetData {
     pub id: SetId,
     pub time: Option<Timestamp>,
+    pub completed: Option<Timestamp>,
+    // Bracket round; losers rounds are negative
+    pub round: Option<i64>,
     pub teams: Teams<PlayerData>,
     pub winner: usize,
+    pub dq: bool,
+    // Games won by each team, if reported
+    pub scores: Option<Vec<u64>>,
+    pub games: Vec<GameData>,
 }
 
-impl QueryUnwrap<EventSetsVars> for EventSets {
+impl<'a> QueryUnwrap<EventSetsVars<'a>> for EventSets {
     type Unwrapped = EventSetsResponse;
 
     // This might be the most spaghetti code I've ever written
     fn unwrap_response(response: GraphQlResponse<EventSets>) -> Option<EventSetsResponse> {
-        let response_sets = response.data?.event?.sets?;
+        let response_event = response.data?.event?;
+        let response_sets = response_event.sets?;
 
         let sets = response_sets
             .nodes
             .into_iter()
-            .filter_map(|set| {
+            .map(|set| {
                 let winner_id = set.winner_id?;
                 let winner = set.slots.iter().position(|slot| {
                     slot.entrant
@@ -116,7 +203,37 @@ impl QueryUnwrap<EventSetsVars> for EventSets {
                         .map(|id| id.0 == winner_id as u64)
                         .unwrap_or(false)
                 })?;
-                let teams = set
+                // start.gg marks a disqualified entrant with a score of -1
+                let dq = set.display_score.as_deref() == Some("DQ")
+                    || set.slots.iter().any(|slot| {
+                        slot.standing
+                            .as_ref()
+                            .and_then(|s| s.stats.as_ref())
+                            .and_then(|s| s.score.as_ref())
+                            .and_then(|s| s.value)
+                            .is_some_and(|value| value < 0.0)
+                    });
+                let scores = set
+                    .slots
+                    .iter()
+                    .map(|slot| {
+                        let value = slot
+                            .standing
+                            .as_ref()?
+                            .stats
+                            .as_ref()?
+                            .score
+                            .as_ref()?
+                            .value?;
+                        (value >= 0.0).then_some(value as u64)
+                    })
+                    .collect::<Option<Vec<_>>>();
+                let entrants = set
+                    .slots
+                    .iter()
+                    .map(|slot| slot.entrant.as_ref().and_then(|x| x.id))
+                    .collect::<Vec<_>>();
+                let teams: Teams<PlayerData> = set
                     .slots
                     .into_iter()
                     .map(|slot| {
@@ -129,24 +246,79 @@ impl QueryUnwrap<EventSetsVars> for EventSets {
                                     id: p_.id?,
                                     name: p_.gamer_tag?,
                                     prefix: p_.prefix.filter(|pr| !pr.is_empty()),
-                                    discrim: p_.user?.discriminator?,
+                                    discrim: p_.user.and_then(|user| user.discriminator),
                                 })
                             })
                             .try_collect()
                     })
                     .try_collect()?;
+                let team_of = |entrant: Option<EntrantId>| {
+                    entrants
+                        .iter()
+                        .position(|&id| id.is_some() && id == entrant)
+                };
+                let games = set
+                    .games
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, game)| GameData {
+                        number: game.order_num.map_or(i as u64 + 1, |n| n as u64),
+                        winner: game
+                            .winner_id
+                            .and_then(|id| team_of(Some(EntrantId(id as u64)))),
+                        // Selections in team sets cannot be attributed to a
+                        // single player
+                        selections: game
+                            .selections
+                            .into_iter()
+                            .filter_map(|selection| {
+                                let team = team_of(selection.entrant?.id)?;
+                                let [player] = &teams[team][..] else {
+                                    return None;
+                                };
+                                let character = selection.character?;
+                                Some((
+                                    player.id,
+                                    CharacterData {
+                                        id: character.id?,
+                                        name: character.name?,
+                                    },
+                                ))
+                            })
+                            .collect(),
+                    })
+                    .collect();
                 Some(SetData {
                     id: set.id?,
                     time: set.start_at.or(set.started_at),
+                    completed: set.completed_at,
+                    round: set.round.map(|r| r as i64),
                     teams,
                     winner,
+                    dq,
+                    scores,
+                    games,
                 })
             })
             .collect::<Vec<_>>();
 
         Some(EventSetsResponse {
-            pages: response_sets.page_info?.total_pages? as u64,
+            total: response_sets.page_info?.total? as u64,
+            phase_groups: response_event
+                .phase_groups
+                .into_iter()
+                .filter_map(|group| group.id)
+                .collect(),
             sets,
         })
     }
+
+    // Sets from a completed event are final
+    fn cacheable(response: &GraphQlResponse<EventSets>) -> bool {
+        response
+            .data
+            .as_ref()
+            .and_then(|data| data.event.as_ref())
+            .is_some_and(|event| event.state == Some(ActivityState::Completed))
+    }
 }
@@ -0,0 +1,119 @@
+// A mock start.gg server for tests.
+//
+// Responses are read from `tests/fixtures/<OPERATION>.json`, which holds a
+// list of `{ "variables": ..., "response": ... }` objects. A request is
+// answered with the first fixture whose variables are all present in the
+// request, so more specific fixtures should be listed first.
+
+use super::{disable_rate_limit, set_endpoint};
+use serde_json::{json, Map, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::OnceLock;
+use std::thread;
+
+static MOCK_URL: OnceLock<String> = OnceLock::new();
+
+/// Start the mock server if it is not already running, and direct all
+/// queries to it without rate limiting.
+pub fn start() -> &'static str {
+    MOCK_URL.get_or_init(|| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/gql/alpha", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let _ = stream.map(handle);
+            }
+        });
+
+        set_endpoint(url.clone());
+        disable_rate_limit();
+        url
+    })
+}
+
+// Variable names are compared ignoring case and underscores, so fixtures can
+// use either the Rust or GraphQL spelling
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|&c| c != '_')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn matches(fixture: &Map<String, Value>, request: &Map<String, Value>) -> bool {
+    fixture.iter().all(|(name, value)| {
+        request
+            .iter()
+            .any(|(name_, value_)| normalize(name) == normalize(name_) && value == value_)
+    })
+}
+
+fn respond(request: &Value) -> Value {
+    let operation = request["operationName"].as_str().unwrap_or_default();
+    let variables = request["variables"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default();
+
+    let path = format!(
+        "{}/tests/fixtures/{}.json",
+        env!("CARGO_MANIFEST_DIR"),
+        operation
+    );
+    let fixtures = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<Vec<Value>>(&s).ok())
+        .unwrap_or_default();
+
+    fixtures
+        .into_iter()
+        .find(|fixture| {
+            fixture["variables"]
+                .as_object()
+                .is_none_or(|vars| matches(vars, &variables))
+        })
+        .map(|fixture| fixture["response"].clone())
+        .unwrap_or_else(
+            || json!({ "data": null, "errors": [{ "message": "No fixture matches request" }] }),
+        )
+}
+
+fn handle(stream: TcpStream) {
+    let mut reader = BufReader::new(&stream);
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((header, value)) = line.split_once(':') {
+            if header.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+
+    let response = serde_json::from_slice(&body)
+        .map(|request| respond(&request))
+        .unwrap_or(Value::Null)
+        .to_string();
+
+    let _ = write!(
+        &stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.len(),
+        response
+    );
+}
@@ -54,11 +54,21 @@ pub struct EventId(pub u64);
 #[repr(transparent)]
 pub struct EntrantId(pub u64);
 
+#[derive(cynic::Scalar, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cynic(graphql_type = "ID")]
+#[repr(transparent)]
+pub struct PhaseGroupId(pub u64);
+
 #[derive(cynic::Scalar, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[cynic(graphql_type = "ID")]
 #[repr(transparent)]
 pub struct PlayerId(pub u64);
 
+#[derive(cynic::Scalar, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cynic(graphql_type = "ID")]
+#[repr(transparent)]
+pub struct CharacterId(pub u64);
+
 #[derive(cynic::Scalar, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[cynic(graphql_type = "ID")]
 #[repr(transparent)]
@@ -67,3 +77,16 @@ pub struct SetId(pub StringOrInt);
 #[derive(cynic::Scalar, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
 pub struct Timestamp(pub u64);
+
+// Enum Types
+
+#[derive(cynic::Enum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ActivityState {
+    Created,
+    Active,
+    Completed,
+    Ready,
+    Invalid,
+    Called,
+    Queued,
+}
@@ -1,5 +1,5 @@
 use super::scalars::*;
-use super::QueryUnwrap;
+use super::{Page, QueryUnwrap};
 use cynic::GraphQlResponse;
 use schema::schema;
 
@@ -7,12 +7,25 @@ use schema::schema;
 
 #[derive(cynic::QueryVariables, Debug, Copy, Clone)]
 pub struct TournamentEventsVars<'a> {
+    pub page: i32,
+    pub per_page: i32,
+
     pub after_date: Timestamp,
     pub before_date: Timestamp,
 
-    pub game_id: VideogameId,
+    pub game_ids: Option<&'a [VideogameId]>,
     pub country: Option<&'a str>,
     pub state: Option<&'a str>,
+    pub location: Option<TournamentLocationFilter<'a>>,
+    pub has_online_events: Option<bool>,
+}
+
+#[derive(cynic::InputObject, Debug, Copy, Clone)]
+pub struct TournamentLocationFilter<'a> {
+    // Latitude and longitude, separated by a comma
+    pub distance_from: Option<&'a str>,
+    // e.g. "50mi"
+    pub distance: Option<&'a str>,
 }
 
 // Query
@@ -21,16 +34,18 @@ pub struct TournamentEventsVars<'a> {
 #[cynic(graphql_type = "Query", variables = "TournamentEventsVars")]
 pub struct TournamentEvents {
     #[arguments(query: {
-        page: 1,
-        perPage: 225,
+        page: $page,
+        perPage: $per_page,
         sortBy: "startAt asc",
         filter: {
             past: true,
             afterDate: $after_date,
             beforeDate: $before_date,
-            videogameIds: [$game_id],
+            videogameIds: $game_ids,
             countryCode: $country,
-            addrState: $state
+            addrState: $state,
+            location: $location,
+            hasOnlineEvents: $has_online_events
         }})]
     tournaments: Option<TournamentConnection>,
 }
@@ -38,16 +53,29 @@ pub struct TournamentEvents {
 #[derive(cynic::QueryFragment, Debug)]
 #[cynic(variables = "TournamentEventsVars")]
 struct TournamentConnection {
+    page_info: Option<PageInfo>,
     #[cynic(flatten)]
     nodes: Vec<Tournament>,
 }
 
+#[derive(cynic::QueryFragment, Debug)]
+struct PageInfo {
+    total: Option<i32>,
+}
+
 #[derive(cynic::QueryFragment, Debug)]
 #[cynic(variables = "TournamentEventsVars")]
 struct Tournament {
     id: Option<TournamentId>,
+    name: Option<String>,
+    slug: Option<String>,
     start_at: Option<Timestamp>,
-    #[arguments(limit: 99999, filter: { videogameId: [$game_id] })]
+    city: Option<String>,
+    addr_state: Option<String>,
+    country_code: Option<String>,
+    is_online: Option<bool>,
+    num_attendees: Option<i32>,
+    #[arguments(limit: 99999, filter: { videogameId: $game_ids })]
     #[cynic(flatten)]
     events: Vec<Event>,
 }
@@ -57,7 +85,18 @@ struct Tournament {
 struct Event {
     id: Option<EventId>,
     slug: Option<String>,
+    name: Option<String>,
     start_at: Option<Timestamp>,
+    is_online: Option<bool>,
+    num_entrants: Option<i32>,
+    #[cynic(rename = "type")]
+    event_type: Option<i32>,
+    videogame: Option<Videogame>,
+}
+
+#[derive(cynic::QueryFragment, Debug)]
+struct Videogame {
+    id: Option<VideogameId>,
 }
 
 // Unwrap
@@ -69,27 +108,62 @@ pub struct TournamentData {
     pub events: Vec<EventData>,
 }
 
+#[derive(Debug, Clone)]
+pub struct TournamentInfo {
+    pub id: TournamentId,
+    pub name: String,
+    pub slug: String,
+    pub time: Timestamp,
+    pub city: Option<String>,
+    pub state: Option<String>,
+    pub country: Option<String>,
+    pub online: bool,
+    pub entrants: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct EventData {
     pub id: EventId,
     pub slug: String,
+    pub name: String,
     pub time: Timestamp,
+    pub online: bool,
+    pub entrants: u64,
+    // Whether entrants are single players rather than teams
+    pub singles: bool,
+    pub game: Option<VideogameId>,
+    // Unknown if start.gg does not report the event's tournament
+    pub tournament: Option<TournamentInfo>,
 }
 
 impl<'a> QueryUnwrap<TournamentEventsVars<'a>> for TournamentEvents {
-    type Unwrapped = Vec<TournamentData>;
+    type Unwrapped = Page<TournamentData>;
 
-    fn unwrap_response(response: GraphQlResponse<TournamentEvents>) -> Option<Vec<TournamentData>> {
+    fn unwrap_response(
+        response: GraphQlResponse<TournamentEvents>,
+    ) -> Option<Page<TournamentData>> {
         let response_tournaments = response.data?.tournaments?;
 
-        Some(
-            response_tournaments
+        Some(Page {
+            total: response_tournaments.page_info?.total? as u64,
+            items: response_tournaments
                 .nodes
                 .into_iter()
-                .filter_map(|tour| {
-                    Some(TournamentData {
+                .map(|tour| {
+                    let info = TournamentInfo {
                         id: tour.id?,
+                        name: tour.name.unwrap_or_default(),
+                        slug: tour.slug?,
                         time: tour.start_at?,
+                        city: tour.city,
+                        state: tour.addr_state,
+                        country: tour.country_code,
+                        online: tour.is_online.unwrap_or(false),
+                        entrants: tour.num_attendees.unwrap_or(0) as u64,
+                    };
+                    Some(TournamentData {
+                        id: info.id,
+                        time: info.time,
                         events: tour
                             .events
                             .into_iter()
@@ -97,13 +171,19 @@ impl<'a> QueryUnwrap<TournamentEventsVars<'a>> for TournamentEvents {
                                 Some(EventData {
                                     id: event.id?,
                                     slug: event.slug?,
+                                    name: event.name.unwrap_or_default(),
                                     time: event.start_at?,
+                                    online: event.is_online.unwrap_or(info.online),
+                                    entrants: event.num_entrants.unwrap_or(0) as u64,
+                                    singles: event.event_type.is_none_or(|t| t == 1),
+                                    game: event.videogame.and_then(|game| game.id),
+                                    tournament: Some(info.clone()),
                                 })
                             })
                             .collect(),
                     })
                 })
                 .collect::<Vec<_>>(),
-        )
+        })
     }
 }
@@ -1,117 +1,463 @@
-use std::thread::sleep;
-use std::time::Duration;
-
 use crate::database::*;
 use crate::error;
+use crate::progress::*;
 use crate::queries::*;
+use chrono::{TimeZone, Utc};
 use itertools::Itertools;
 use sqlite::*;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 // Extract set data
 
+// Page sizes for each query, which are reduced whenever start.gg reports that
+// a page is too complex
+static TOURNAMENTS_PER_PAGE: AtomicU64 = AtomicU64::new(225);
+static SETS_PER_PAGE: AtomicU64 = AtomicU64::new(25);
+
+fn get_filtered_sets(
+    event: EventId,
+    phase_groups: Option<&[PhaseGroupId]>,
+    truncate: bool,
+    auth: &str,
+) -> Option<(Pages<SetData>, Vec<PhaseGroupId>)> {
+    let mut event_groups = Vec::new();
+
+    let sets = fetch_pages(&SETS_PER_PAGE, truncate, |page, per_page| {
+        let response = try_query::<EventSets, _>(
+            EventSetsVars {
+                event,
+                page,
+                per_page,
+                phase_groups,
+            },
+            auth,
+        )?;
+        event_groups = response.phase_groups;
+        Ok(Page {
+            total: response.total,
+            items: response.sets,
+        })
+    })?;
+
+    Some((sets, event_groups))
+}
+
 fn get_event_sets(event: EventId, auth: &str) -> Option<Vec<SetData>> {
-    sleep(Duration::from_millis(700));
+    let (sets, phase_groups) = get_filtered_sets(event, None, false, auth)?;
+    if sets.total <= MAX_RESULTS {
+        return Some(sets.items);
+    }
 
-    let sets_response = run_query::<EventSets, _>(EventSetsVars { event, page: 1 }, auth)?;
+    // Events too large to fetch at once are fetched one phase group at a time
+    if phase_groups.is_empty() {
+        status(&format!(
+            "  (Event ID {} has more sets than start.gg allows fetching)",
+            event.0
+        ));
+        return Some(get_filtered_sets(event, None, true, auth)?.0.items);
+    }
 
-    let pages = sets_response.pages;
-    if pages == 0 {
-        Some(vec![])
-    } else if pages == 1 {
-        Some(sets_response.sets)
-    } else {
-        let mut sets = sets_response.sets;
+    let mut sets = Vec::new();
+    for group in phase_groups {
+        let (group_sets, _) = get_filtered_sets(event, Some(&[group][..]), true, auth)?;
+        if group_sets.total > MAX_RESULTS {
+            status(&format!(
+                "  (Phase group ID {} has more sets than start.gg allows fetching)",
+                group.0
+            ));
+        }
+        sets.extend(group_sets.items);
+    }
+    Some(sets)
+}
 
-        for page in 2..=pages {
-            println!("  (Page {})", page);
+// Number of threads fetching event sets at once
+const FETCH_WORKERS: usize = 4;
+// How many events the workers may fetch ahead of the event being rated
+const PREFETCH_LIMIT: usize = 16;
 
-            let next_response = run_query::<EventSets, _>(
-                EventSetsVars {
-                    event,
-                    page: page as i32,
-                },
-                auth,
-            )?;
+struct FetchWindow {
+    // Number of events handed to the writer so far
+    applied: usize,
+    stopped: bool,
+}
+
+/// Fetch the sets of several events concurrently, passing each event's sets
+/// to `apply` strictly in the order the events are given. Fetching stops as
+/// soon as `apply` returns an error.
+fn fetch_event_sets<F>(events: &[EventData], auth: &str, mut apply: F) -> sqlite::Result<()>
+where
+    F: FnMut(&EventData, Option<Vec<SetData>>, Duration) -> sqlite::Result<()>,
+{
+    let next = AtomicUsize::new(0);
+    let window = (
+        Mutex::new(FetchWindow {
+            applied: 0,
+            stopped: false,
+        }),
+        Condvar::new(),
+    );
+    let (sender, receiver) = mpsc::channel();
 
-            sleep(Duration::from_millis(700));
+    thread::scope(|scope| {
+        for _ in 0..FETCH_WORKERS.min(events.len()) {
+            let (next, window, sender) = (&next, &window, sender.clone());
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= events.len() {
+                    break;
+                }
 
-            sets.extend(next_response.sets);
+                // Wait until the writer has caught up
+                let mut state = window.0.lock().unwrap();
+                while !state.stopped && i >= state.applied + PREFETCH_LIMIT {
+                    state = window.1.wait(state).unwrap();
+                }
+                if state.stopped {
+                    break;
+                }
+                drop(state);
+
+                let start = Instant::now();
+                let sets = get_event_sets(events[i].id, auth);
+                if sender.send((i, sets, start.elapsed())).is_err() {
+                    break;
+                }
+            });
         }
+        drop(sender);
 
-        Some(sets)
-    }
+        // Results arrive in any order, and are held until their turn
+        let mut fetched = BTreeMap::new();
+        let result = (0..events.len()).try_for_each(|i| {
+            while !fetched.contains_key(&i) {
+                let (j, sets, duration) = receiver.recv().expect("Fetching thread panicked");
+                fetched.insert(j, (sets, duration));
+            }
+            let (sets, duration) = fetched.remove(&i).unwrap();
+
+            apply(&events[i], sets, duration)?;
+
+            window.0.lock().unwrap().applied += 1;
+            window.1.notify_all();
+            Ok(())
+        });
+
+        window.0.lock().unwrap().stopped = true;
+        window.1.notify_all();
+        result
+    })
 }
 
-fn get_tournament_events(
+fn get_location_tournaments(
     metadata: &DatasetMetadata,
-    before: Timestamp,
+    location: Option<&Location>,
+    (after, before): (Timestamp, Timestamp),
     auth: &str,
-) -> Option<Vec<EventData>> {
-    println!("Accessing tournaments...");
-
-    let mut after = metadata.last_sync;
-
-    let tour_response = run_query::<TournamentEvents, _>(
-        TournamentEventsVars {
-            after_date: after,
-            before_date: before,
-            game_id: metadata.game_id,
-            country: metadata.country.as_deref(),
-            state: metadata.state.as_deref(),
-        },
-        auth,
-    )?;
+) -> Option<Vec<TournamentData>> {
+    let game_ids = metadata
+        .games
+        .iter()
+        .map(|game| game.id)
+        .collect::<Vec<_>>();
 
-    let mut cont = !tour_response.is_empty();
-    after = if tour_response.iter().any(|tour| tour.time != after) {
-        tour_response.last().unwrap().time
-    } else {
-        Timestamp(after.0 + 1)
+    let (country, state, radius) = match location {
+        None => (None, None, None),
+        Some(Location::Region { country, state }) => (Some(&country[..]), state.as_deref(), None),
+        Some(Location::Radius {
+            latitude,
+            longitude,
+            distance,
+        }) => (
+            None,
+            None,
+            Some((format!("{},{}", latitude, longitude), &distance[..])),
+        ),
     };
+    // No location filter is given unless there is a radius
+    let location_filter = radius
+        .as_ref()
+        .map(|(center, distance)| TournamentLocationFilter {
+            distance_from: Some(center),
+            distance: Some(distance),
+        });
 
-    let mut tournaments = tour_response;
+    // Tournaments without online events can be filtered out by start.gg
+    let has_online_events = (metadata.online == OnlinePolicy::Online).then_some(true);
 
-    let mut page: u64 = 1;
-    while cont {
-        page += 1;
-        println!("  (Page {})", page);
+    // A window of a single second cannot be split any further
+    let splittable = before.0 > after.0 + 1;
 
-        let next_response = run_query::<TournamentEvents, _>(
+    let tournaments = fetch_pages(&TOURNAMENTS_PER_PAGE, !splittable, |page, per_page| {
+        try_query::<TournamentEvents, _>(
             TournamentEventsVars {
+                page,
+                per_page,
                 after_date: after,
                 before_date: before,
-                game_id: metadata.game_id,
-                country: metadata.country.as_deref(),
-                state: metadata.state.as_deref(),
+                game_ids: Some(&game_ids),
+                country,
+                state,
+                location: location_filter,
+                has_online_events,
             },
             auth,
-        )?;
-
-        cont = !next_response.is_empty();
-        after = if next_response.iter().any(|tour| tour.time != after) {
-            next_response.last().unwrap().time
-        } else {
-            Timestamp(after.0 + 1)
-        };
+        )
+    })?;
 
-        tournaments.extend(next_response);
+    if tournaments.total <= MAX_RESULTS {
+        Some(tournaments.items)
+    } else if splittable {
+        // Split the window in half; tournaments on the boundary may be returned
+        // by both halves, and are deduplicated later
+        let middle = Timestamp(after.0 + (before.0 - after.0) / 2);
+        let mut tournaments = get_location_tournaments(metadata, location, (after, middle), auth)?;
+        tournaments.extend(get_location_tournaments(
+            metadata,
+            location,
+            (middle, before),
+            auth,
+        )?);
+        Some(tournaments)
+    } else {
+        status(&format!(
+            "  ({} tournaments start at the same time; only {} could be fetched)",
+            tournaments.total,
+            tournaments.items.len()
+        ));
+        Some(tournaments.items)
     }
+}
+
+fn get_tournament_events(
+    metadata: &DatasetMetadata,
+    window: (Timestamp, Timestamp),
+    auth: &str,
+) -> Option<Vec<EventData>> {
+    status("Accessing tournaments...");
+
+    let mut tournaments = if metadata.locations.is_empty() {
+        get_location_tournaments(metadata, None, window, auth)?
+    } else {
+        let mut tournaments = Vec::new();
+        for location in metadata.locations.iter() {
+            tournaments.extend(get_location_tournaments(
+                metadata,
+                Some(location),
+                window,
+                auth,
+            )?);
+        }
+        tournaments
+    };
 
-    println!("Deduplicating...");
+    status("Deduplicating...");
+
+    // Locations can overlap, and pages can overlap at their boundaries
+    tournaments.sort_by_key(|tour| tour.time);
 
     Some(
         tournaments
             .into_iter()
-            .group_by(|tour| tour.time)
-            .into_iter()
-            .flat_map(|(_, group)| group.into_iter().unique_by(|tour| tour.id))
+            .unique_by(|tour| tour.id)
             .flat_map(|tour| tour.events)
+            .filter(|event| metadata.online.allows(event.online))
             .collect::<Vec<_>>(),
     )
 }
 
+/// The weight of a set in a dataset's ratings based on how many games it was
+/// won by, ranging from just over 0.5 for a close set to 1 for a sweep.
+fn margin_weight(metadata: &DatasetMetadata, results: &SetData) -> f64 {
+    if !metadata.weight_margin {
+        return 1.0;
+    }
+
+    match results.scores.as_deref() {
+        Some(&[score1, score2]) if score1 + score2 > 0 => {
+            0.5 + 0.5 * score1.abs_diff(score2) as f64 / (score1 + score2) as f64
+        }
+        // Sets without reported game counts are counted fully
+        _ => 1.0,
+    }
+}
+
+/// Decide whether a set can be rated in a dataset, returning the reason if
+/// not.
+fn set_skip_reason(metadata: &DatasetMetadata, results: &SetData) -> Option<&'static str> {
+    // Disqualifications and forfeits say nothing about skill
+    if results.dq {
+        return Some("dq");
+    }
+
+    // Free-for-all matches are currently not supported, and team matches are
+    // only supported if the dataset rates them
+    let teams = &results.teams;
+    if teams.len() != 2 || teams.iter().any(|team| team.is_empty()) {
+        return Some("format");
+    }
+    if !metadata.rate_teams && teams.iter().any(|team| team.len() != 1) {
+        return Some("teams");
+    }
+    None
+}
+
+/// Decide whether a dataset's inclusion rules exclude an event, returning
+/// the reason if so.
+fn skip_reason(rules: &EventRules, event: &EventData) -> Option<String> {
+    let slug = event.slug.to_lowercase();
+    let name = event.name.to_lowercase();
+    if let Some(keyword) = rules
+        .blocklist
+        .iter()
+        .find(|k| slug.contains(&k.to_lowercase()) || name.contains(&k.to_lowercase()))
+    {
+        return Some(format!("matches keyword \"{}\"", keyword));
+    }
+    if event.entrants < rules.min_entrants {
+        return Some(format!(
+            "{} entrants, minimum is {}",
+            event.entrants, rules.min_entrants
+        ));
+    }
+    if rules.singles_only && !event.singles {
+        return Some("not a singles event".to_owned());
+    }
+    None
+}
+
+/// Find whether an event has been explicitly included in or excluded from a
+/// dataset. An entry for the event itself takes precedence over an entry for
+/// its tournament.
+fn event_filter(filters: &[(String, bool)], slug: &str) -> Option<bool> {
+    filters
+        .iter()
+        .filter(|(prefix, _)| {
+            slug == prefix
+                || slug
+                    .strip_prefix(&prefix[..])
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|&(_, include)| include)
+}
+
 // Dataset syncing
 
+/// What syncing a dataset does with an event.
+enum EventPlan {
+    AlreadySynced,
+    Skip(String),
+    Fetch,
+}
+
+/// Decide what syncing a dataset should do with each event found.
+fn plan_events(
+    connection: &Connection,
+    dataset: &str,
+    metadata: &DatasetMetadata,
+    events: Vec<EventData>,
+) -> sqlite::Result<Vec<(EventData, EventPlan)>> {
+    let filters = get_event_filters(connection, dataset)?;
+
+    events
+        .into_iter()
+        .map(|event| {
+            if event_synced(connection, dataset, event.id)? {
+                return Ok((event, EventPlan::AlreadySynced));
+            }
+
+            // Explicitly included events bypass the dataset's inclusion rules
+            let reason = match event_filter(&filters, &event.slug) {
+                Some(true) => None,
+                Some(false) => Some("excluded".to_owned()),
+                None => skip_reason(&metadata.rules, &event),
+            };
+            let plan = match reason {
+                Some(reason) => EventPlan::Skip(reason),
+                None => EventPlan::Fetch,
+            };
+            Ok((event, plan))
+        })
+        .try_collect()
+}
+
+/// Show what syncing a dataset would do, without fetching any sets or writing
+/// to the database.
+pub fn preview_sync(
+    connection: &Connection,
+    dataset: &str,
+    metadata: &DatasetMetadata,
+    before: Timestamp,
+    auth: &str,
+) -> sqlite::Result<()> {
+    let before = get_pending_sync(connection, dataset)?.unwrap_or(before);
+
+    let start_requests = requests_made();
+    let events = get_tournament_events(metadata, (metadata.last_sync, before), auth)
+        .unwrap_or_else(|| error("Could not access start.gg", 1));
+    // Syncing fetches the same pages of tournaments again
+    let tournament_requests = requests_made() - start_requests;
+
+    let num_events = events.len();
+    let events = plan_events(connection, dataset, metadata, events)?;
+
+    let format_date = |time: Timestamp| {
+        Utc.timestamp_opt(time.0 as i64, 0)
+            .unwrap()
+            .format("%m/%d/%Y")
+    };
+    println!(
+        "\n\x1b[1m{}\x1b[0m: {} - {}",
+        dataset,
+        format_date(metadata.last_sync),
+        format_date(before)
+    );
+
+    let mut num_included = 0;
+    let mut num_entrants = 0;
+    let mut set_requests = 0;
+    for (event, plan) in events.iter() {
+        print!(
+            "{}  {}  ({} entrants)",
+            format_date(event.time),
+            event.slug,
+            event.entrants
+        );
+        match plan {
+            EventPlan::AlreadySynced => {
+                println!(" \x1b[2m- skipped: already synced\x1b[0m")
+            }
+            EventPlan::Skip(reason) => println!(" \x1b[2m- skipped: {}\x1b[0m", reason),
+            EventPlan::Fetch => {
+                println!();
+                num_included += 1;
+                num_entrants += event.entrants;
+                // A double elimination bracket has about two sets per entrant
+                set_requests += (2 * event.entrants)
+                    .div_ceil(SETS_PER_PAGE.load(Ordering::Relaxed))
+                    .max(1);
+            }
+        }
+    }
+
+    println!(
+        "\n{} of {} events would be synced ({} entrants)",
+        num_included, num_events, num_entrants
+    );
+    println!(
+        "Estimated requests: {} ({} for tournaments, {} for sets)",
+        tournament_requests + set_requests,
+        tournament_requests,
+        set_requests
+    );
+    Ok(())
+}
+
 fn update_from_set(
     connection: &Connection,
     dataset: &str,
@@ -119,67 +465,238 @@ fn update_from_set(
     event_time: Timestamp,
     results: SetData,
 ) -> sqlite::Result<()> {
-    let teams = results.teams;
-
-    // Non-singles matches are currently not supported
-    if teams.len() != 2 || teams[0].len() != 1 || teams[1].len() != 1 {
+    if set_skip_reason(metadata, &results).is_some() {
         return Ok(());
     }
 
-    let players = teams.into_iter().flatten().collect::<Vec<_>>();
-    add_players(connection, dataset, &players)?;
+    let teams = &results.teams;
 
-    let player1 = players[0].id;
-    let player2 = players[1].id;
+    let players = teams.iter().flatten().cloned().collect::<Vec<_>>();
+    add_players(connection, dataset, &players)?;
 
     // Time-adjust all variances associated with each player
-    let time = results.time.unwrap_or(event_time);
-    adjust_for_time(connection, dataset, player1, metadata.var_const, time)?;
-    adjust_for_time(connection, dataset, player2, metadata.var_const, time)?;
+    let time = set_time(&results, event_time);
+    players.iter().try_for_each(|player| {
+        adjust_for_time(connection, dataset, player.id, metadata.var_const, time)
+    })?;
+
+    // A team match is rated as a match between every pair of opponents, with
+    // each pairing counting as a fraction of a set
+    let pairs = teams[0]
+        .iter()
+        .cartesian_product(teams[1].iter())
+        .map(|(player1, player2)| (player1.id, player2.id))
+        .collect::<Vec<_>>();
+    let share = 1.0 / pairs.len() as f64;
+    // The margin of victory changes how far ratings move, not the prediction
+    let weight = margin_weight(metadata, &results) * share;
+
+    let mut team_advantage = 0.0;
+    for &(player1, player2) in pairs.iter() {
+        let (advantage, _) = match get_network_data(connection, dataset, player1, player2) {
+            Err(e) => Err(e)?,
+            Ok(None) => initialize_edge(
+                connection,
+                dataset,
+                player1,
+                player2,
+                metadata.decay_const,
+                time,
+            )?,
+            Ok(Some(adv)) => adv,
+        };
+        team_advantage += advantage * share;
+    }
+
+    let scores = results
+        .scores
+        .as_ref()
+        .map(|scores| (scores[results.winner], scores[1 - results.winner]));
 
-    let (advantage, variance) = match get_network_data(connection, dataset, player1, player2) {
-        Err(e) => Err(e)?,
-        Ok(None) => initialize_edge(
+    for &(player1, player2) in pairs.iter() {
+        let (_, variance) = get_network_data(connection, dataset, player1, player2)?.unwrap();
+
+        let delta = glicko_adjust(
             connection,
             dataset,
             player1,
             player2,
+            team_advantage,
+            variance,
+            results.winner,
             metadata.decay_const,
+            weight,
+        )?;
+
+        let (winner, loser, delta) = if results.winner == 0 {
+            (player1, player2, -delta)
+        } else {
+            (player2, player1, delta)
+        };
+        add_set_result(
+            connection,
+            dataset,
+            &results.id,
             time,
-        )?,
-        Ok(Some(adv)) => adv,
-    };
+            (winner, loser),
+            scores,
+            delta,
+        )?;
+    }
 
-    // println!("{}, {} - {}, {}", player1.0, player2.0, advantage, variance);
+    Ok(())
+}
 
-    glicko_adjust(
-        connection,
-        dataset,
-        &results.id,
-        player1,
-        player2,
-        advantage,
-        variance,
-        results.winner,
-        metadata.decay_const,
-    )?;
+/// Store an event's sets in a single transaction, without rating them yet.
+fn store_event(
+    connection: &Connection,
+    dataset: &str,
+    metadata: &DatasetMetadata,
+    event: &EventData,
+    sets: Vec<SetData>,
+) -> sqlite::Result<EventStats> {
+    transaction(connection, || {
+        add_event(connection, event)?;
 
-    set_player_set_counts(
-        connection,
-        dataset,
-        player1,
-        results.winner == 0,
-        &results.id,
-    )?;
-    set_player_set_counts(
-        connection,
-        dataset,
-        player2,
-        results.winner == 1,
-        &results.id,
-    )?;
+        let mut stats = EventStats::default();
+        sets.iter().try_for_each(|set| {
+            match set_skip_reason(metadata, set) {
+                Some(reason) => stats.skip(reason),
+                None => stats.rated += 1,
+            }
 
-    Ok(())
+            add_set(connection, &set.id, event.id)?;
+            add_set_data(connection, set, set.time.unwrap_or(event.time))
+        })?;
+
+        mark_event_synced(connection, dataset, event.id, false)?;
+        Ok(stats)
+    })
+}
+
+/// Update a dataset's ratings from the sets of every event stored but not yet
+/// rated, along with any sets reported late for events that were already
+/// rated, in the order given by `set_order`. Sets from events running at the
+/// same time are interleaved, which rating each event on its own cannot do.
+fn rate_stored_sets(
+    connection: &Connection,
+    dataset: &str,
+    metadata: &DatasetMetadata,
+    late_sets: Vec<(EventId, SetData)>,
+) -> sqlite::Result<()> {
+    let mut sets = get_unrated_sets(connection, dataset)?
+        .into_iter()
+        .map(|(_, set)| set)
+        .collect::<Vec<_>>();
+    if sets.is_empty() && late_sets.is_empty() {
+        return Ok(());
+    }
+
+    status(&format!("Rating {} sets...", sets.len() + late_sets.len()));
+
+    transaction(connection, || {
+        for (event, set) in late_sets {
+            add_set(connection, &set.id, event)?;
+            add_set_data(connection, &set, set.time.unwrap())?;
+            sets.push(set);
+        }
+
+        // Stored sets always have a time
+        sets.sort_by_cached_key(|set| set_order(set, set.time.unwrap()));
+        sets.into_iter().try_for_each(|set| {
+            let time = set.time.unwrap();
+            update_from_set(connection, dataset, metadata, time, set)
+        })?;
+        mark_events_rated(connection, dataset)
+    })
+}
+
+fn set_winners(set: &SetData) -> Vec<PlayerId> {
+    let mut winners = set.teams[set.winner]
+        .iter()
+        .map(|player| player.id)
+        .collect::<Vec<_>>();
+    winners.sort();
+    winners
+}
+
+/// Fetch the sets of events synced within `lookback` seconds before the last
+/// sync of a dataset, and correct any sets whose results changed since.
+///
+/// Sets reported since are returned with their events, to be rated along with
+/// the rest of the sync.
+fn recheck_events(
+    connection: &Connection,
+    dataset: &str,
+    metadata: &DatasetMetadata,
+    lookback: u64,
+    auth: &str,
+) -> sqlite::Result<Vec<(EventId, SetData)>> {
+    status("Re-checking recent events...");
+
+    let after = Timestamp(
+        metadata
+            .last_sync
+            .0
+            .saturating_sub(lookback)
+            .max(metadata.start.0),
+    );
+    let events = get_tournament_events(metadata, (after, metadata.last_sync), auth)
+        .unwrap_or_else(|| error("Could not access start.gg", 1));
+
+    let mut to_check = Vec::new();
+    for event in events {
+        if event_synced(connection, dataset, event.id)? {
+            to_check.push(event);
+        }
+    }
+
+    let mut late_sets = Vec::new();
+    let mut changed = Vec::new();
+    fetch_event_sets(&to_check, auth, |event, sets, _| {
+        let sets = sets.unwrap_or_else(|| error("Could not access start.gg", 1));
+
+        transaction(connection, || {
+            sets.into_iter().try_for_each(|mut set| {
+                // Whether a set was rated is checked against this dataset, as
+                // its results may have been stored by a different one
+                match get_set_winners(connection, dataset, &set.id)? {
+                    // Sets that would not be rated are only stored
+                    None if set_skip_reason(metadata, &set).is_some() => {
+                        add_set(connection, &set.id, event.id)?;
+                        add_set_data(connection, &set, set.time.unwrap_or(event.time))?;
+                    }
+                    // Sets reported after the event was synced are rated with
+                    // the rest of the sync
+                    None => {
+                        set.time = Some(set.time.unwrap_or(event.time));
+                        late_sets.push((event.id, set));
+                    }
+                    Some(winners) if set.dq || winners != set_winners(&set) => {
+                        add_set_data(connection, &set, set.time.unwrap_or(event.time))?;
+                        changed.push(set.id);
+                    }
+                    Some(_) => (),
+                }
+                Ok(())
+            })
+        })
+    })?;
+
+    if !late_sets.is_empty() {
+        status(&format!(
+            "Found {} sets reported after their event was synced",
+            late_sets.len()
+        ));
+    }
+    if !changed.is_empty() {
+        status(&format!(
+            "Corrected the results of {} sets; run 'startrnr dataset recompute {:?}' to update ratings from them",
+            changed.len(),
+            dataset
+        ));
+    }
+    Ok(late_sets)
 }
 
 pub fn sync_dataset(
@@ -187,38 +704,1337 @@ pub fn sync_dataset(
     dataset: &str,
     metadata: DatasetMetadata,
     before: Timestamp,
+    lookback: u64,
     auth: &str,
 ) -> sqlite::Result<()> {
-    let events = get_tournament_events(&metadata, before, auth)
+    // If a previous sync of this dataset was interrupted, resume it with the
+    // same time window so that no tournaments are skipped or counted twice
+    let before = match get_pending_sync(connection, dataset)? {
+        Some(until) => {
+            status(&format!("Resuming interrupted sync of {}...", dataset));
+            until
+        }
+        None => {
+            set_pending_sync(connection, dataset, before)?;
+            before
+        }
+    };
+
+    // Events from before the last sync may have been unfinished at the time
+    let late_sets = if lookback > 0 && metadata.last_sync != metadata.start {
+        recheck_events(connection, dataset, &metadata, lookback, auth)?
+    } else {
+        Vec::new()
+    };
+
+    let events = get_tournament_events(&metadata, (metadata.last_sync, before), auth)
         .unwrap_or_else(|| error("Could not access start.gg", 1));
 
-    connection.execute("BEGIN;")?;
+    // Every event found is stored, including those that will be skipped
+    transaction(connection, || {
+        events
+            .iter()
+            .try_for_each(|event| add_event(connection, event))
+    })?;
 
-    let num_events = events.len();
-    for (i, event) in events.into_iter().enumerate() {
-        println!(
-            "Accessing sets from event ID {}... ({}/{})",
-            event.id.0,
-            i + 1,
-            num_events
+    let mut progress = SyncProgress::new(dataset, events.len());
+
+    let mut to_fetch = Vec::new();
+    for (event, plan) in plan_events(connection, dataset, &metadata, events)? {
+        match plan {
+            EventPlan::AlreadySynced => progress.already_synced(),
+            EventPlan::Skip(reason) => progress.event_skipped(&event, reason),
+            EventPlan::Fetch => to_fetch.push(event),
+        }
+    }
+    progress.draw();
+
+    // Sets are fetched in the background while earlier events are stored
+    fetch_event_sets(&to_fetch, auth, |event, sets, duration| {
+        let start = Instant::now();
+
+        let sets = sets.unwrap_or_else(|| {
+            error(
+                "Could not access start.gg; run sync again to resume from this event",
+                1,
+            )
+        });
+
+        // Each event is committed on its own, so an interrupted sync only
+        // loses the event it was working on
+        let stats = store_event(connection, dataset, &metadata, event, sets)?;
+
+        progress.event_synced(event, stats, duration + start.elapsed());
+        progress.draw();
+        Ok(())
+    })?;
+
+    progress.finish();
+
+    // Sets are only rated once every event in the window is stored, so that
+    // they can be rated in order across events
+    rate_stored_sets(connection, dataset, &metadata, late_sets)?;
+
+    finish_sync(connection, dataset, before)
+}
+
+/// Add a single event to a dataset, regardless of whether the dataset would
+/// include it when syncing.
+pub fn import_event(
+    connection: &Connection,
+    dataset: &str,
+    metadata: &DatasetMetadata,
+    event: &EventData,
+    auth: &str,
+) -> sqlite::Result<()> {
+    println!("Accessing sets from event ID {}...", event.id.0);
+
+    let sets =
+        get_event_sets(event.id, auth).unwrap_or_else(|| error("Could not access start.gg", 1));
+
+    let stats = store_event(connection, dataset, metadata, event, sets)?;
+    rate_stored_sets(connection, dataset, metadata, Vec::new())?;
+    println!("Rated {} sets", stats.rated);
+    if let Some(num_dq) = stats.skipped.get("dq") {
+        println!("Excluded {} DQ'd or forfeited sets", num_dq);
+    }
+    Ok(())
+}
+
+/// Count the sets of a dataset that rebuilding it from its locally stored sets
+/// would lose, or return `None` if it was synced before sets were stored
+/// locally and cannot be rebuilt at all.
+pub fn sets_lost_by_rebuild(
+    connection: &Connection,
+    dataset: &str,
+    metadata: &DatasetMetadata,
+) -> sqlite::Result<Option<u64>> {
+    if count_dataset_events(connection, dataset)? == 0 && metadata.last_sync != metadata.start {
+        return Ok(None);
+    }
+    count_missing_sets(connection, dataset).map(Some)
+}
+
+/// Rebuild the rating network of a dataset from its locally stored sets.
+pub fn recompute_dataset(
+    connection: &Connection,
+    dataset: &str,
+    metadata: &DatasetMetadata,
+) -> sqlite::Result<()> {
+    transaction(connection, || {
+        rebuild_dataset(connection, dataset, metadata)
+    })
+}
+
+fn rebuild_dataset(
+    connection: &Connection,
+    dataset: &str,
+    metadata: &DatasetMetadata,
+) -> sqlite::Result<()> {
+    println!("Loading sets...");
+
+    let filters = get_event_filters(connection, dataset)?;
+    let (mut sets, excluded): (Vec<_>, Vec<_>) = get_dataset_sets(connection, dataset)?
+        .into_iter()
+        .partition(|(slug, _)| event_filter(&filters, slug) != Some(false));
+
+    if !excluded.is_empty() {
+        println!("Leaving out {} sets from excluded events", excluded.len());
+    }
+    println!("Updating ratings from {} sets...", sets.len());
+
+    update_parameters(
+        connection,
+        dataset,
+        metadata.decay_const,
+        metadata.var_const,
+        metadata.weight_margin,
+    )?;
+    reset_dataset(connection, dataset)?;
+    sets.sort_by_cached_key(|(_, set)| set_order(set, set.time.unwrap()));
+    sets.into_iter().try_for_each(|(_, set)| {
+        let time = set.time.unwrap();
+        update_from_set(connection, dataset, metadata, time, set)
+    })?;
+    mark_events_rated(connection, dataset)
+}
+
+/// Link a player without a start.gg account to one. If the account already
+/// has a player of its own, the two players are merged and every dataset with
+/// the unlinked player is recomputed, all in a single transaction.
+///
+/// Returns the player that was merged into, if any, along with the datasets
+/// that were left as they were because recomputing them would lose sets that
+/// are not stored locally.
+pub fn link_player(
+    connection: &Connection,
+    player: PlayerId,
+    account: &str,
+) -> sqlite::Result<Option<(PlayerData, Vec<String>)>> {
+    transaction(connection, || {
+        let Ok(linked) = get_player_from_discrim(connection, account) else {
+            set_player_discrim(connection, player, account)?;
+            return Ok(None);
+        };
+
+        // The account has sets of its own, so both histories must be replayed
+        let mut datasets = Vec::new();
+        let mut not_recomputed = Vec::new();
+        for dataset in list_dataset_names(connection)? {
+            if !player_in_dataset(connection, &dataset, player)? {
+                continue;
+            }
+            let metadata = get_metadata(connection, &dataset)?.unwrap();
+            if sets_lost_by_rebuild(connection, &dataset, &metadata)? == Some(0) {
+                datasets.push((dataset, metadata));
+            } else {
+                not_recomputed.push(dataset);
+            }
+        }
+
+        merge_players(connection, player, linked.id)?;
+
+        for (dataset, metadata) in datasets {
+            println!("\nRecomputing {}...", dataset);
+            rebuild_dataset(connection, &dataset, &metadata)?;
+        }
+        Ok(Some((linked, not_recomputed)))
+    })
+}
+
+// Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::*;
+    use crate::queries::mock;
+    use std::ops::Deref;
+    use std::path::{Path, PathBuf};
+
+    // A directory for a test's files, which is removed when the test ends
+    struct TempDir(PathBuf);
+
+    impl Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for TempDir {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn temp_dir(name: &str) -> TempDir {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("startrnr-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        TempDir(dir)
+    }
+
+    // A datasets file in its own directory, which is closed before the
+    // directory is removed
+    struct TestDatasets {
+        connection: Connection,
+        dir: TempDir,
+    }
+
+    impl Deref for TestDatasets {
+        type Target = Connection;
+
+        fn deref(&self) -> &Connection {
+            &self.connection
+        }
+    }
+
+    // Open an empty datasets file, with queries sent to the mock server
+    fn temp_datasets(name: &str) -> TestDatasets {
+        mock::start();
+
+        let dir = temp_dir(name);
+        TestDatasets {
+            connection: open_datasets(&dir).unwrap(),
+            dir,
+        }
+    }
+
+    // Open a datasets file with a dataset "test" synced from the mock server
+    fn synced_datasets(name: &str) -> TestDatasets {
+        let datasets = temp_datasets(name);
+        new_dataset(&datasets, "test", metadata()).unwrap();
+        sync_dataset(&datasets, "test", metadata(), Timestamp(1701000000), 0, "").unwrap();
+        datasets
+    }
+
+    // An event that the mock server has sets for
+    fn event(id: u64) -> EventData {
+        EventData {
+            id: EventId(id),
+            slug: format!("tournament/test-3/event/{}", id),
+            name: format!("Event {}", id),
+            time: Timestamp(1700700000),
+            online: false,
+            entrants: 4,
+            singles: true,
+            game: Some(VideogameId(0)),
+            tournament: None,
+        }
+    }
+
+    #[test]
+    fn search_games() {
+        mock::start();
+
+        let games =
+            run_query::<VideogameSearch, _>(VideogameSearchVars { name: "Test" }, "").unwrap();
+
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].id, VideogameId(1));
+        assert_eq!(games[1].slug, "game/test-2");
+    }
+
+    #[test]
+    fn transaction_rollback() {
+        let connection = mock_datasets().unwrap();
+
+        let result = transaction(&connection, || {
+            new_dataset(&connection, "test", metadata())?;
+            Err::<(), _>(sqlite::Error {
+                code: None,
+                message: None,
+            })
+        });
+        assert!(result.is_err());
+        assert!(list_datasets(&connection).unwrap().is_empty());
+
+        // The connection is left outside of any transaction
+        transaction(&connection, || new_dataset(&connection, "test", metadata())).unwrap();
+        assert_eq!(list_datasets(&connection).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn sync_and_recompute() {
+        let connection = synced_datasets("sync");
+
+        let synced = get_metadata(&connection, "test").unwrap().unwrap();
+        assert_eq!(synced.last_sync, Timestamp(1701000000));
+        assert_eq!(get_pending_sync(&connection, "test").unwrap(), None);
+        assert!(event_synced(&connection, "test", EventId(101)).unwrap());
+        assert!(event_synced(&connection, "test", EventId(102)).unwrap());
+
+        let one = get_player_from_discrim(&connection, "aaaa0001").unwrap();
+        assert_eq!(one.id, PlayerId(1));
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(1)).unwrap(),
+            (2, 0)
+        );
+        assert_eq!(
+            get_matchup_set_counts(&connection, "test", PlayerId(3), PlayerId(2)).unwrap(),
+            (1, 0)
         );
 
-        add_event(connection, event.id, &event.slug)?;
+        let advantage = get_network_data(&connection, "test", PlayerId(1), PlayerId(2))
+            .unwrap()
+            .unwrap()
+            .0;
+        assert!(advantage < 0.0);
 
-        let mut sets =
-            get_event_sets(event.id, auth).unwrap_or_else(|| error("Could not access start.gg", 1));
+        // Replaying the stored sets should give the same network
+        recompute_dataset(&connection, "test", &synced).unwrap();
+        assert_eq!(
+            get_network_data(&connection, "test", PlayerId(1), PlayerId(2))
+                .unwrap()
+                .unwrap()
+                .0,
+            advantage
+        );
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(1)).unwrap(),
+            (2, 0)
+        );
+    }
 
-        if sets.is_empty() {
-            println!("  No valid sets");
-        } else {
-            println!("  Updating ratings from event...");
+    #[test]
+    fn online_policy() {
+        // Event 102 is online, and event 101 is not
+        let connection = temp_datasets("online");
+        for (name, online, expected) in [
+            ("both", OnlinePolicy::Both, [true, true]),
+            ("online", OnlinePolicy::Online, [false, true]),
+            ("offline", OnlinePolicy::Offline, [true, false]),
+        ] {
+            let metadata = || DatasetMetadata {
+                online,
+                ..metadata()
+            };
+            new_dataset(&connection, name, metadata()).unwrap();
+            sync_dataset(&connection, name, metadata(), Timestamp(1701000000), 0, "").unwrap();
+
+            let synced = [
+                event_synced(&connection, name, EventId(101)).unwrap(),
+                event_synced(&connection, name, EventId(102)).unwrap(),
+            ];
+            assert_eq!(synced, expected, "{}", name);
+            assert_eq!(
+                get_metadata(&connection, name).unwrap().unwrap().online,
+                online
+            );
+        }
+    }
+
+    #[test]
+    fn team_sets() {
+        let connection = temp_datasets("teams");
+        let rate_doubles = |dataset, metadata: &DatasetMetadata| {
+            for set in get_event_sets(EventId(105), "").unwrap() {
+                update_from_set(&connection, dataset, metadata, Timestamp(1700800000), set)
+                    .unwrap();
+            }
+        };
 
-            sets.sort_by_key(|set| set.time);
-            sets.into_iter().try_for_each(|set| {
-                add_set(connection, &set.id, event.id)?;
-                update_from_set(connection, dataset, &metadata, event.time, set)
-            })?;
+        // Datasets that do not rate teams skip team sets
+        new_dataset(&connection, "singles", metadata()).unwrap();
+        rate_doubles("singles", &metadata());
+        for player in players(4) {
+            assert_eq!(
+                get_player_set_counts(&connection, "singles", player.id).unwrap(),
+                (0, 0)
+            );
+        }
+
+        // Otherwise each player is rated against each of their opponents
+        let metadata = || DatasetMetadata {
+            rate_teams: true,
+            ..metadata()
+        };
+        new_dataset(&connection, "teams", metadata()).unwrap();
+        rate_doubles("teams", &metadata());
+        for player in players(4) {
+            let expected = if player.id.0 <= 2 { (1, 0) } else { (0, 1) };
+            assert_eq!(
+                get_player_set_counts(&connection, "teams", player.id).unwrap(),
+                expected
+            );
         }
+        assert_eq!(
+            get_matchup_set_counts(&connection, "teams", PlayerId(2), PlayerId(4)).unwrap(),
+            (1, 0)
+        );
+        assert!(
+            get_network_data(&connection, "teams", PlayerId(1), PlayerId(3))
+                .unwrap()
+                .is_some()
+        );
+        // Teammates are not rated against each other
+        assert!(
+            get_network_data(&connection, "teams", PlayerId(1), PlayerId(2))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn dq_sets() {
+        let connection = synced_datasets("dq");
+
+        // Set 1004 was won by DQ, and is stored but not rated
+        let stored = get_dataset_sets(&connection, "test").unwrap();
+        assert!(
+            stored
+                .iter()
+                .find(|(_, set)| set.id == SetId(StringOrInt::Int(1004)))
+                .unwrap()
+                .1
+                .dq
+        );
+        assert_eq!(
+            get_matchup_set_counts(&connection, "test", PlayerId(1), PlayerId(3)).unwrap(),
+            (1, 0)
+        );
+
+        // DQs stay excluded when the dataset is recomputed
+        recompute_dataset(&connection, "test", &metadata()).unwrap();
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(1)).unwrap(),
+            (2, 0)
+        );
+        assert_eq!(
+            get_matchup_set_counts(&connection, "test", PlayerId(1), PlayerId(3)).unwrap(),
+            (1, 0)
+        );
+    }
+
+    #[test]
+    fn event_rules() {
+        let connection = temp_datasets("rules");
+        let mut metadata = metadata();
+        metadata.rules.blocklist = vec![String::from("test-2")];
+        new_dataset(&connection, "test", metadata).unwrap();
+
+        let stored = get_metadata(&connection, "test").unwrap().unwrap();
+        assert_eq!(stored.rules.blocklist, vec![String::from("test-2")]);
+
+        sync_dataset(&connection, "test", stored, Timestamp(1701000000), 0, "").unwrap();
+
+        assert!(event_synced(&connection, "test", EventId(101)).unwrap());
+        assert!(!event_synced(&connection, "test", EventId(102)).unwrap());
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(3)).unwrap(),
+            (0, 1)
+        );
+    }
+
+    #[test]
+    fn dataset_locations() {
+        use cynic::QueryBuilder;
+
+        // Without a radius, no location filter is given
+        let vars = |location| TournamentEventsVars {
+            page: 1,
+            per_page: 25,
+            after_date: Timestamp(1),
+            before_date: Timestamp(2),
+            game_ids: None,
+            country: Some("US"),
+            state: None,
+            location,
+            has_online_events: None,
+        };
+        let operation = TournamentEvents::build(vars(None));
+        let variables = serde_json::to_value(operation.variables).unwrap();
+        assert_eq!(variables["location"], serde_json::Value::Null);
+
+        let operation = TournamentEvents::build(vars(Some(TournamentLocationFilter {
+            distance_from: Some("47.6,-122.3"),
+            distance: Some("50mi"),
+        })));
+        let variables = serde_json::to_value(operation.variables).unwrap();
+        assert_eq!(
+            variables["location"],
+            serde_json::json!({ "distanceFrom": "47.6,-122.3", "distance": "50mi" })
+        );
+
+        let region = Location::Region {
+            country: String::from("US"),
+            state: Some(String::from("WA")),
+        };
+        let radius = Location::Radius {
+            latitude: 47.6,
+            longitude: -122.3,
+            distance: String::from("50mi"),
+        };
+        let metadata = |locations| DatasetMetadata {
+            locations,
+            ..metadata()
+        };
+
+        let connection = temp_datasets("locations");
+        new_dataset(&connection, "region", metadata(vec![region.clone()])).unwrap();
+        sync_dataset(
+            &connection,
+            "region",
+            metadata(vec![region.clone()]),
+            Timestamp(1701000000),
+            0,
+            "",
+        )
+        .unwrap();
+        assert!(event_synced(&connection, "region", EventId(101)).unwrap());
+        assert!(!event_synced(&connection, "region", EventId(102)).unwrap());
+
+        // Tournaments found by more than one location are only synced once
+        let locations = vec![region, radius];
+        new_dataset(&connection, "test", metadata(locations.clone())).unwrap();
+        assert_eq!(
+            get_metadata(&connection, "test")
+                .unwrap()
+                .unwrap()
+                .locations
+                .len(),
+            2
+        );
+        sync_dataset(
+            &connection,
+            "test",
+            metadata(locations),
+            Timestamp(1701000000),
+            0,
+            "",
+        )
+        .unwrap();
+
+        assert!(event_synced(&connection, "test", EventId(101)).unwrap());
+        assert!(event_synced(&connection, "test", EventId(102)).unwrap());
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(3)).unwrap(),
+            (1, 1)
+        );
+    }
+
+    #[test]
+    fn event_filters() {
+        let connection = synced_datasets("filters");
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(3)).unwrap(),
+            (1, 1)
+        );
+
+        // Excluding a tournament removes its events on recompute
+        set_event_filter(&connection, "test", "tournament/test-2", false).unwrap();
+        recompute_dataset(&connection, "test", &metadata()).unwrap();
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(3)).unwrap(),
+            (0, 1)
+        );
+
+        // Including an event overrides the exclusion of its tournament
+        set_event_filter(&connection, "test", "tournament/test-2/event/singles", true).unwrap();
+        recompute_dataset(&connection, "test", &metadata()).unwrap();
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(3)).unwrap(),
+            (1, 1)
+        );
+
+        // Removing a rule only removes one of the given kind
+        assert!(!remove_event_filter(&connection, "test", "tournament/test-2", true).unwrap());
+        assert!(remove_event_filter(&connection, "test", "tournament/test-2", false).unwrap());
+        assert_eq!(
+            get_event_filters(&connection, "test").unwrap(),
+            vec![(String::from("tournament/test-2/event/singles"), true)]
+        );
+    }
+
+    #[test]
+    fn import_single_event() {
+        let connection = temp_datasets("import");
+        new_dataset(&connection, "test", metadata()).unwrap();
+
+        let event = run_query::<EventInfo, _>(
+            EventInfoVars {
+                slug: "tournament/test-2/event/singles",
+            },
+            "",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(event.id, EventId(102));
+
+        import_event(&connection, "test", &metadata(), &event, "").unwrap();
+        assert!(event_synced(&connection, "test", EventId(102)).unwrap());
+        assert!(get_unrated_sets(&connection, "test").unwrap().is_empty());
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(3)).unwrap(),
+            (1, 0)
+        );
+
+        // Syncing afterwards should not count the imported event again
+        sync_dataset(
+            &connection,
+            "test",
+            metadata(),
+            Timestamp(1701000000),
+            0,
+            "",
+        )
+        .unwrap();
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(3)).unwrap(),
+            (1, 1)
+        );
+
+        // Events can be imported without full information on their tournament
+        let event = run_query::<EventInfo, _>(
+            EventInfoVars {
+                slug: "tournament/test-3/event/singles",
+            },
+            "",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(event.tournament.is_none());
+        assert!(event.online);
+
+        import_event(&connection, "test", &metadata(), &event, "").unwrap();
+        assert!(event_synced(&connection, "test", EventId(103)).unwrap());
+        let history = get_player_history(&connection, "test", PlayerId(3), 1).unwrap();
+        assert_eq!(history[0].set.id, SetId(StringOrInt::Int(1006)));
+        assert!(history[0].tournament.is_none());
+    }
+
+    #[test]
+    fn game_scores() {
+        let connection = synced_datasets("scores");
+
+        let sets = get_dataset_sets(&connection, "test").unwrap();
+        assert_eq!(sets[0].1.scores, Some(vec![2, 0]));
+        // A DQ'd entrant has a score of -1, which is not a game count
+        assert_eq!(sets[3].1.scores, None);
+
+        let mut weighted = metadata();
+        weighted.weight_margin = true;
+        assert_eq!(margin_weight(&weighted, &sets[0].1), 1.0);
+        assert_eq!(margin_weight(&weighted, &sets[1].1), 0.75);
+        assert_eq!(margin_weight(&metadata(), &sets[1].1), 1.0);
+    }
+
+    #[test]
+    fn margin_weights() {
+        let weighted = DatasetMetadata {
+            weight_margin: true,
+            ..metadata()
+        };
+        let set = |scores: Option<Vec<u64>>| SetData {
+            id: SetId(StringOrInt::Int(1)),
+            time: None,
+            completed: None,
+            round: None,
+            teams: vec![],
+            winner: 0,
+            dq: false,
+            scores,
+            games: vec![],
+        };
+
+        assert_eq!(margin_weight(&weighted, &set(Some(vec![3, 0]))), 1.0);
+        assert_eq!(margin_weight(&weighted, &set(Some(vec![1, 3]))), 0.75);
+        // Equal scores, as in a set that was ended early
+        assert_eq!(margin_weight(&weighted, &set(Some(vec![1, 1]))), 0.5);
+        assert_eq!(margin_weight(&weighted, &set(Some(vec![0, 0]))), 1.0);
+        // Sets without reported game counts are counted fully, including those
+        // with no display score and DQs, whose entrant has a score of -1
+        assert_eq!(margin_weight(&weighted, &set(None)), 1.0);
+        let dq = SetData {
+            dq: true,
+            ..set(None)
+        };
+        assert_eq!(margin_weight(&weighted, &dq), 1.0);
+
+        assert_eq!(margin_weight(&metadata(), &set(Some(vec![1, 3]))), 1.0);
+    }
+
+    #[test]
+    fn character_selections() {
+        let connection = synced_datasets("characters");
+
+        assert_eq!(
+            get_character_usage(&connection, "test", PlayerId(1)).unwrap(),
+            vec![(String::from("Fox"), 1), (String::from("Marth"), 1)]
+        );
+        assert_eq!(
+            get_character_matchups(&connection, "test", PlayerId(2), PlayerId(1)).unwrap(),
+            vec![
+                (String::from("Falco"), String::from("Fox"), 0, 1),
+                (String::from("Falco"), String::from("Marth"), 0, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn json_log() {
+        use serde_json::{json, Value};
+
+        let connection = temp_datasets("log");
+        new_dataset(&connection, "test", metadata()).unwrap();
+
+        let mut log = Vec::new();
+        let mut progress =
+            SyncProgress::with_output("test", 2, LogFormat::Json, Box::new(&mut log));
+
+        progress.event_skipped(&event(101), String::from("excluded"));
+        let sets = get_event_sets(EventId(102), "").unwrap();
+        let stats = store_event(&connection, "test", &metadata(), &event(102), sets).unwrap();
+        progress.event_synced(&event(102), stats, Duration::from_millis(1500));
+        progress.finish();
+
+        // Each record is a single line of JSON
+        let records = String::from_utf8(log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 3);
+
+        assert_eq!(
+            records[0],
+            json!({
+                "type": "event",
+                "dataset": "test",
+                "id": 101,
+                "slug": "tournament/test-3/event/101",
+                "excluded": "excluded",
+            })
+        );
+        assert_eq!(
+            records[1],
+            json!({
+                "type": "event",
+                "dataset": "test",
+                "id": 102,
+                "slug": "tournament/test-3/event/102",
+                "sets": 2,
+                "skipped": { "dq": 1 },
+                "duration": 1.5,
+            })
+        );
+
+        assert_eq!(records[2]["type"], "sync");
+        assert_eq!(records[2]["events"], 1);
+        assert_eq!(records[2]["events_skipped"], 1);
+        assert_eq!(records[2]["sets"], 2);
+        assert_eq!(records[2]["skipped"], json!({ "dq": 1 }));
+        assert!(records[2]["requests"].is_u64());
+        assert!(records[2]["duration"].is_f64());
+    }
+
+    #[test]
+    fn fetch_order() {
+        mock::start();
+
+        let ids = [105, 101, 102, 101, 105, 102];
+        let events = ids.map(event);
+
+        // Sets are applied in the order the events were given, whichever
+        // finishes fetching first
+        let mut applied = Vec::new();
+        fetch_event_sets(&events, "", |event, sets, _| {
+            applied.push((event.id.0, sets.unwrap().len()));
+            Ok(())
+        })
+        .unwrap();
+        let num_sets = |id| match id {
+            101 => 2,
+            102 => 3,
+            _ => 1,
+        };
+        assert_eq!(applied, ids.map(|id| (id, num_sets(id))));
+
+        // An error stops fetching and is returned
+        let mut applied = 0;
+        let result = fetch_event_sets(&events, "", |_, _, _| {
+            applied += 1;
+            if applied == 3 {
+                Err(sqlite::Error {
+                    code: None,
+                    message: Some(String::from("stop")),
+                })
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(result.unwrap_err().message.as_deref(), Some("stop"));
+        assert_eq!(applied, 3);
+    }
+
+    #[test]
+    fn recheck_changed_results() {
+        let connection = synced_datasets("recheck");
+
+        // Simulate a result that was corrected on start.gg after syncing
+        let set = SetId(StringOrInt::Int(1001));
+        connection
+            .execute(r#"UPDATE "test_set_results" SET winner = 2, loser = 1 WHERE set_id = '1001'"#)
+            .unwrap();
+        connection
+            .execute("UPDATE set_data SET winner = 1 WHERE id = '1001'")
+            .unwrap();
+
+        // Simulate a set that was stored by another dataset, but never rated
+        // in this one
+        connection
+            .execute(r#"DELETE FROM "test_set_results" WHERE set_id = '1002'"#)
+            .unwrap();
+
+        let synced = get_metadata(&connection, "test").unwrap().unwrap();
+        sync_dataset(
+            &connection,
+            "test",
+            synced,
+            Timestamp(1702000000),
+            1000000,
+            "",
+        )
+        .unwrap();
+        assert_eq!(
+            get_set_winners(&connection, "test", &SetId(StringOrInt::Int(1002))).unwrap(),
+            Some(vec![PlayerId(1)])
+        );
+
+        // Corrected sets are only rated again when the dataset is recomputed
+        assert_eq!(
+            get_set_winners(&connection, "test", &set).unwrap(),
+            Some(vec![PlayerId(2)])
+        );
+        recompute_dataset(&connection, "test", &metadata()).unwrap();
+        assert_eq!(
+            get_set_winners(&connection, "test", &set).unwrap(),
+            Some(vec![PlayerId(1)])
+        );
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(1)).unwrap(),
+            (2, 0)
+        );
+    }
+
+    #[test]
+    fn recheck_changed_dqs() {
+        let connection = synced_datasets("recheck_dqs");
+
+        // Simulate a set that was stored as a DQ and is no longer one, and a
+        // set that was stored as played and has since been marked as a DQ
+        connection
+            .execute(r#"DELETE FROM "test_set_results" WHERE set_id = '1001'"#)
+            .unwrap();
+        connection
+            .execute("UPDATE set_data SET dq = 1 WHERE id = '1001'")
+            .unwrap();
+        connection
+            .execute("UPDATE set_data SET dq = 0 WHERE id = '1004'")
+            .unwrap();
+
+        let synced = get_metadata(&connection, "test").unwrap().unwrap();
+        sync_dataset(
+            &connection,
+            "test",
+            synced,
+            Timestamp(1702000000),
+            1000000,
+            "",
+        )
+        .unwrap();
+
+        let stored = get_dataset_sets(&connection, "test").unwrap();
+        let dq = |id| {
+            stored
+                .iter()
+                .find(|(_, set)| set.id == SetId(StringOrInt::Int(id)))
+                .unwrap()
+                .1
+                .dq
+        };
+        assert!(!dq(1001));
+        assert!(dq(1004));
+        assert_eq!(
+            get_set_winners(&connection, "test", &SetId(StringOrInt::Int(1001))).unwrap(),
+            Some(vec![PlayerId(1)])
+        );
+    }
+
+    #[test]
+    fn adaptive_pagination() {
+        let items = (0..30).collect::<Vec<u64>>();
+        let fetch = |page: i32, per_page: i32| {
+            if per_page > 8 {
+                return Err(QueryError::TooComplex);
+            }
+            let start = ((page - 1) * per_page) as usize;
+            Ok(Page {
+                total: items.len() as u64,
+                items: items
+                    .iter()
+                    .copied()
+                    .skip(start)
+                    .take(per_page as usize)
+                    .map(Some)
+                    .collect(),
+            })
+        };
+
+        let per_page = AtomicU64::new(20);
+        let pages = fetch_pages(&per_page, false, fetch).unwrap();
+        assert_eq!(pages.items, items);
+        assert_eq!(per_page.load(Ordering::Relaxed), 5);
+
+        // Queries over start.gg's cap stop after the first page
+        let pages = fetch_pages(&AtomicU64::new(5), false, |_, _| {
+            Ok(Page {
+                total: MAX_RESULTS + 1,
+                items: vec![Some(0); 5],
+            })
+        })
+        .unwrap();
+        assert_eq!(pages.items.len(), 5);
+        assert_eq!(pages.total, MAX_RESULTS + 1);
+
+        // Truncated queries fetch up to the cap, including a last partial page
+        let mut pages_fetched = 0;
+        let pages = fetch_pages(&AtomicU64::new(225), true, |page, per_page| {
+            pages_fetched += 1;
+            let start = ((page - 1) * per_page) as u64;
+            Ok(Page {
+                total: 2 * MAX_RESULTS,
+                items: (start..(start + per_page as u64).min(MAX_RESULTS))
+                    .map(Some)
+                    .collect(),
+            })
+        })
+        .unwrap();
+        assert_eq!(pages.items.len() as u64, MAX_RESULTS);
+        assert_eq!(pages_fetched, 45);
+
+        // Results that could not be read still count towards the position
+        let fetch = |page: i32, per_page: i32| {
+            let start = (page - 1) * per_page;
+            Ok(Page {
+                total: 12,
+                items: (start..(start + per_page).min(12))
+                    .map(|i| (i % 4 != 0).then_some(i))
+                    .collect(),
+            })
+        };
+        let pages = fetch_pages(&AtomicU64::new(5), false, fetch).unwrap();
+        assert_eq!(pages.items, [1, 2, 3, 5, 6, 7, 9, 10, 11]);
+
+        // A page with fewer results than expected ends the query
+        let mut calls = 0;
+        let pages = fetch_pages(&AtomicU64::new(5), false, |_, _| {
+            calls += 1;
+            Ok(Page {
+                total: 10,
+                items: vec![Some(0); 3],
+            })
+        })
+        .unwrap();
+        assert_eq!(pages.items.len(), 3);
+        assert!(calls <= 2);
+    }
+
+    #[test]
+    fn complexity_errors() {
+        mock::start();
+
+        let vars = |event, per_page| EventSetsVars {
+            event: EventId(event),
+            page: 1,
+            per_page,
+            phase_groups: None,
+        };
+        assert!(matches!(
+            try_query::<EventSets, _>(vars(103, 25), ""),
+            Err(QueryError::TooComplex)
+        ));
+        assert!(matches!(
+            try_query::<EventSets, _>(vars(104, 25), ""),
+            Err(QueryError::Failed)
+        ));
+
+        // The page size is reduced until the query succeeds
+        let sets = get_event_sets(EventId(103), "").unwrap();
+        assert_eq!(sets.len(), 1);
+        assert!(SETS_PER_PAGE.load(Ordering::Relaxed) < 25);
+    }
+
+    #[test]
+    fn unlinked_players() {
+        let connection = synced_datasets("unlinked");
+
+        // Players without an account are identified by their player ID
+        let four = get_player(&connection, PlayerId(4)).unwrap();
+        assert_eq!(four.discrim, None);
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(4)).unwrap(),
+            (1, 0)
+        );
+
+        // A dataset that rated sets it does not have stored cannot be
+        // recomputed without losing them
+        new_dataset(&connection, "partial", metadata()).unwrap();
+        sync_dataset(
+            &connection,
+            "partial",
+            metadata(),
+            Timestamp(1701000000),
+            0,
+            "",
+        )
+        .unwrap();
+        connection
+            .execute("DELETE FROM dataset_events WHERE dataset = 'partial' AND event = 101")
+            .unwrap();
+        assert_eq!(count_missing_sets(&connection, "partial").unwrap(), 2);
+
+        // Linking to an account that has its own player merges the two
+        let (linked, not_recomputed) = link_player(&connection, PlayerId(4), "aaaa0001")
+            .unwrap()
+            .unwrap();
+        assert_eq!(linked.id, PlayerId(1));
+        assert_eq!(not_recomputed, vec![String::from("partial")]);
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(1)).unwrap(),
+            (3, 0)
+        );
+        assert!(!player_in_dataset(&connection, "test", PlayerId(4)).unwrap());
+        assert_eq!(
+            get_player_set_counts(&connection, "partial", PlayerId(1)).unwrap(),
+            (2, 0)
+        );
+    }
+
+    #[test]
+    fn link_new_account() {
+        let connection = synced_datasets("link");
+
+        // Linking to an account with no player of its own keeps the player
+        assert!(link_player(&connection, PlayerId(4), "aaaa0004")
+            .unwrap()
+            .is_none());
+        let four = get_player_from_discrim(&connection, "aaaa0004").unwrap();
+        assert_eq!(four.id, PlayerId(4));
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(4)).unwrap(),
+            (1, 0)
+        );
+    }
+
+    #[test]
+    fn set_ordering() {
+        let connection = synced_datasets("order");
+
+        let mut sets = get_dataset_sets(&connection, "test")
+            .unwrap()
+            .into_iter()
+            .map(|(_, set)| set)
+            .collect::<Vec<_>>();
+        let order = |sets: &mut Vec<SetData>| {
+            sets.sort_by_cached_key(|set| set_order(set, Timestamp(0)));
+            sets.iter()
+                .map(|set| set.id.0.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // Set 1001 was completed after set 1002 started
+        assert_eq!(order(&mut sets)[..2], ["1002", "1001"]);
+
+        // Sets completed at the same time are ordered by round, then by ID
+        for set in sets.iter_mut() {
+            set.completed = Some(Timestamp(1));
+        }
+        assert_eq!(order(&mut sets), ["1001", "1004", "1003", "1002", "1005"]);
+    }
+
+    #[test]
+    fn player_history() {
+        let connection = synced_datasets("history");
+
+        let history = get_player_history(&connection, "test", PlayerId(3), 10).unwrap();
+        assert_eq!(history.len(), 3);
+
+        // Newest sets come first, in the reverse of the order they were rated
+        let order = |record: &PlayerSetRecord| set_order(&record.set, Timestamp(0));
+        assert!(history.windows(2).all(|w| order(&w[0]) > order(&w[1])));
+        assert_eq!(history[0].set.id, SetId(StringOrInt::Int(1004)));
+        assert!(history[0].set.dq);
+        let tournament = history[0].tournament.as_ref().unwrap();
+        assert_eq!(tournament.name, "Test Tournament 2");
+        assert_eq!(tournament.city.as_deref(), Some("Seattle"));
+
+        assert_eq!(history[2].event, "Singles");
+        assert_eq!(history[2].team, 1);
+        assert_eq!(
+            history[2].tournament.as_ref().unwrap().slug,
+            "tournament/test-1"
+        );
+
+        assert_eq!(
+            get_player_history(&connection, "test", PlayerId(3), 1)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn set_results_migration() {
+        let TestDatasets { connection, dir } = synced_datasets("results");
+
+        // Replace the dataset's tables with their layout before set results
+        // were stored in a table
+        connection
+            .execute(format!(
+                r#"UPDATE schema_version SET version = {};
+DROP TABLE "test_set_results";
+DROP TABLE "test_network";
+DROP TABLE "test_players";
+
+CREATE TABLE "test_players" (
+    id INTEGER PRIMARY KEY REFERENCES players,
+    sets_won TEXT NOT NULL DEFAULT '',
+    sets_count_won INTEGER AS (length(sets_won) - length(replace(sets_won, ';', ''))),
+    sets_lost TEXT NOT NULL DEFAULT '',
+    sets_count_lost INTEGER AS (length(sets_lost) - length(replace(sets_lost, ';', '')))
+) STRICT;
+CREATE TABLE "test_network" (
+    player_A INTEGER NOT NULL,
+    player_B INTEGER NOT NULL,
+    advantage REAL NOT NULL,
+    variance REAL NOT NULL,
+    last_updated INTEGER NOT NULL,
+    sets_A TEXT NOT NULL DEFAULT '',
+    sets_count_A INTEGER AS (length(sets_A) - length(replace(sets_A, ';', ''))),
+    sets_B TEXT NOT NULL DEFAULT '',
+    sets_count_B INTEGER AS (length(sets_B) - length(replace(sets_B, ';', ''))),
+    PRIMARY KEY (player_A, player_B)
+) STRICT;
+CREATE INDEX "test_network_B" ON "test_network" (player_B);
+
+INSERT INTO "test_players" (id, sets_won, sets_lost) VALUES
+    (1, '1001;1002;', ''), (2, '', '1001;1003;'), (3, '1003;', '1002;');
+INSERT INTO "test_network" VALUES
+    (1, 2, -1.5, 0.5, 1700000000, '1001;', ''),
+    (1, 3, -0.5, 0.5, 1700000000, '1002;', ''),
+    (2, 3, 0.5, 0.5, 1700000000, '', '1003;');"#,
+                SCHEMA_VERSION - 1
+            ))
+            .unwrap();
+        drop(connection);
+
+        let connection = open_datasets(&dir).unwrap();
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(1)).unwrap(),
+            (2, 0)
+        );
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(2)).unwrap(),
+            (0, 2)
+        );
+        assert_eq!(
+            get_matchup_set_counts(&connection, "test", PlayerId(2), PlayerId(3)).unwrap(),
+            (0, 1)
+        );
+        assert_eq!(
+            get_network_data(&connection, "test", PlayerId(1), PlayerId(2)).unwrap(),
+            Some((-1.5, 0.5))
+        );
+
+        // Migrated datasets can still be recomputed
+        recompute_dataset(&connection, "test", &metadata()).unwrap();
+        assert_eq!(
+            get_player_set_counts(&connection, "test", PlayerId(4)).unwrap(),
+            (1, 0)
+        );
+    }
+
+    #[test]
+    fn schema_migrations() {
+        let dir = temp_dir("schema");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // A file created before the schema was versioned
+        let old = sqlite::open(dir.join("datasets.sqlite")).unwrap();
+        old.execute(
+            r#"CREATE TABLE datasets (
+    name TEXT UNIQUE NOT NULL,
+    start INTEGER NOT NULL,
+    end INTEGER,
+    last_sync INTEGER NOT NULL,
+    game_id INTEGER NOT NULL,
+    game_name TEXT NOT NULL,
+    game_slug TEXT NOT NULL,
+    country TEXT,
+    state TEXT,
+    decay_rate REAL NOT NULL,
+    var_const REAL NOT NULL
+) STRICT;
+CREATE TABLE players (
+    id INTEGER PRIMARY KEY,
+    discrim TEXT UNIQUE NOT NULL,
+    name TEXT NOT NULL,
+    prefix TEXT
+) STRICT;
+CREATE TABLE events (
+    id INTEGER PRIMARY KEY,
+    slug TEXT NOT NULL
+) STRICT;
+CREATE TABLE sets (
+    id TEXT PRIMARY KEY,
+    event INTEGER NOT NULL REFERENCES events
+) STRICT, WITHOUT ROWID;
+CREATE TABLE "old_players" (
+    id INTEGER PRIMARY KEY REFERENCES players,
+    sets_won TEXT NOT NULL DEFAULT '',
+    sets_lost TEXT NOT NULL DEFAULT ''
+) STRICT;
+CREATE TABLE "old_network" (
+    player_A INTEGER NOT NULL,
+    player_B INTEGER NOT NULL,
+    advantage REAL NOT NULL,
+    variance REAL NOT NULL,
+    last_updated INTEGER NOT NULL,
+    sets_A TEXT NOT NULL DEFAULT '',
+    sets_B TEXT NOT NULL DEFAULT '',
+    PRIMARY KEY (player_A, player_B)
+) STRICT;
+
+INSERT INTO datasets VALUES
+    ('old', 1, NULL, 1700000000, 0, 'Test Game', 'test', 'US', NULL, 0.5, 0.00000001);
+INSERT INTO players VALUES (1, 'aaaa0001', 'One', NULL), (2, 'aaaa0002', 'Two', NULL);
+INSERT INTO "old_players" VALUES (1, '1;', ''), (2, '', '1;');
+INSERT INTO "old_network" VALUES (1, 2, -1.0, 0.5, 1700000000, '1;', '');"#,
+        )
+        .unwrap();
+        drop(old);
+
+        let connection = open_datasets(&dir).unwrap();
+        assert_eq!(
+            get_schema_version(&connection).unwrap(),
+            Some(SCHEMA_VERSION)
+        );
+        assert!(dir.join("datasets.sqlite.v1.bak").exists());
+
+        let datasets = list_datasets(&connection).unwrap();
+        assert_eq!(datasets.len(), 1);
+        assert_eq!(datasets[0].0, "old");
+        assert_eq!(datasets[0].1.last_sync, Timestamp(1700000000));
+        assert_eq!(datasets[0].1.var_const, 0.00000001);
+        assert!(!datasets[0].1.rate_teams);
+        assert_eq!(
+            get_player_set_counts(&connection, "old", PlayerId(2)).unwrap(),
+            (0, 1)
+        );
+
+        // Players no longer need a discriminator
+        connection
+            .execute("UPDATE players SET discrim = NULL WHERE id = 2")
+            .unwrap();
+        drop(connection);
+
+        // Files that are up to date are not migrated or backed up again
+        std::fs::remove_file(dir.join("datasets.sqlite.v1.bak")).unwrap();
+        let connection = open_datasets(&dir).unwrap();
+        assert!(!dir.join("datasets.sqlite.v1.bak").exists());
+        assert_eq!(get_player(&connection, PlayerId(2)).unwrap().discrim, None);
+
+        // Migrated files have the same layout as new ones
+        let mock = mock_datasets().unwrap();
+        let layout = |connection: &Connection| -> Vec<(String, Option<String>)> {
+            connection
+                .prepare(
+                    "SELECT name, sql FROM sqlite_schema
+                    WHERE tbl_name NOT LIKE 'old_%' ORDER BY name",
+                )
+                .unwrap()
+                .into_iter()
+                .map(|r| {
+                    let r_ = r.unwrap();
+                    (
+                        r_.read::<&str, _>("name").to_owned(),
+                        r_.read::<Option<&str>, _>("sql").map(String::from),
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(layout(&connection), layout(&mock));
+
+        new_dataset(&mock, "test", metadata()).unwrap();
+        assert_eq!(list_datasets(&mock).unwrap().len(), 1);
     }
-    connection.execute("COMMIT;")
 }
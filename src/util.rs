@@ -62,6 +62,33 @@ pub fn ansi_num_color(num: f64, threshold1: f64, threshold2: f64) -> (&'static s
     }
 }
 
+/// Extract the slug of a tournament or event from a start.gg URL or slug,
+/// e.g. `tournament/genesis-9/event/melee-singles`.
+pub fn parse_event_slug(input: &str) -> Option<String> {
+    let input = input.trim().to_lowercase();
+    let start = input.find("tournament/")?;
+    let segments = input[start..]
+        .split(['/', '?', '#'])
+        .take_while(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+
+    match segments[..] {
+        ["tournament", tournament, "event", event, ..] => {
+            Some(format!("tournament/{}/event/{}", tournament, event))
+        }
+        ["tournament", tournament, ..] => Some(format!("tournament/{}", tournament)),
+        _ => None,
+    }
+}
+
+/// The start.gg profile page of a player. Players without an account have no
+/// profile, in which case this is empty and terminal links to it do nothing.
+pub fn player_url(discrim: &Option<String>) -> String {
+    discrim
+        .as_ref()
+        .map_or_else(String::new, |d| format!("https://www.start.gg/user/{}", d))
+}
+
 // Player Input
 
 pub enum PlayerInput {
@@ -100,9 +127,11 @@ pub fn get_player_from_input(connection: &Connection, input: String) -> sqlite::
                         print!("\x1b[2m{}\x1b[22m ", pre);
                     }
                     println!(
-                        "\x1b[4m\x1b]8;;https://www.start.gg/user/{1}\x1b\\\
-\x1b[1m{0}\x1b[22m\x1b]8;;\x1b\\\x1b[0m ({1})",
-                        player.name, player.discrim
+                        "\x1b[4m\x1b]8;;{1}\x1b\\\
+\x1b[1m{0}\x1b[22m\x1b]8;;\x1b\\\x1b[0m ({2})",
+                        player.name,
+                        player_url(&player.discrim),
+                        player.discrim.as_deref().unwrap_or("no account")
                     )
                 }
 